@@ -0,0 +1,96 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use phda::aggregate::lists::{mutate, parse, parse_cached};
+use phda::config::Config;
+use phda::data::AddlistConfig;
+use std::sync::Arc;
+
+const LINES: usize = 300_000;
+const TLDS: [&str; 6] = ["com", "org", "net", "de", "co.uk", "io"];
+
+/// Generates a hosts-like body resembling the lists found in the wild.
+///
+/// Roughly every tenth line is a comment or garbage, the rest are domains in the
+/// plain, hosts and IDN notation, some of them with a `www.` subdomain.
+fn body() -> String {
+    // Simple LCG to keep the input deterministic between runs.
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = || {
+        seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+        (seed >> 33) as usize
+    };
+
+    (0..LINES)
+        .map(|line| {
+            let label = format!("host{}-{}", next() % 50_000, line % 97);
+            let tld = TLDS[next() % TLDS.len()];
+            match next() % 10 {
+                0 => format!("# {label}.{tld} - comment"),
+                1 => format!("||{label}.{tld}^$third-party"),
+                2 => format!("0.0.0.0 www.{label}.{tld}"),
+                3 => format!("127.0.0.1 müller-{label}.{tld} # idn"),
+                4 | 5 => format!("0.0.0.0 {label}.{tld}"),
+                _ => format!("ads.{label}.{tld}"),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn pipeline(c: &mut Criterion) {
+    let raw = body();
+    let domains = parse(raw.clone());
+    let config = AddlistConfig::new(
+        "Bench",
+        Arc::new(Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        }),
+    );
+
+    let mut group = c.benchmark_group("pipeline");
+    group.sample_size(10);
+    group.bench_function("parse", |b| {
+        b.iter_batched(
+            || raw.clone(),
+            |raw| parse(black_box(raw)),
+            BatchSize::LargeInput,
+        )
+    });
+    // Parsing an unchanged body again, which is served from the validation cache.
+    let cache_dir = tempfile::tempdir().unwrap();
+    let cache = cache_dir.path().to_string_lossy().into_owned();
+    parse_cached(
+        &cache,
+        "https://bench.example.local",
+        raw.clone(),
+        false,
+        None,
+    );
+    group.bench_function("parse_cached", |b| {
+        b.iter_batched(
+            || raw.clone(),
+            |raw| {
+                parse_cached(
+                    &cache,
+                    "https://bench.example.local",
+                    black_box(raw),
+                    false,
+                    None,
+                )
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("mutate", |b| {
+        b.iter_batched(
+            || domains.clone(),
+            |domains| mutate(black_box(&config), domains),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
@@ -0,0 +1,220 @@
+use crate::store::{fingerprint, write_atomic};
+use log::warn;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Upper bound of the cooldown of a tripped source.
+const MAX_COOLDOWN: Duration = Duration::from_secs(24 * 60 * 60);
+/// Extension of the state files, so they don't collide with other caches in the same directory.
+const EXTENSION: &str = "breaker";
+
+/// Skips sources that failed repeatedly.
+///
+/// After `threshold` consecutive failures a source is tripped and skipped for a cooldown.
+/// Once the cooldown passed a single probe is allowed. Each further failure doubles the cooldown,
+/// a success resets the source.
+/// With a directory the state of each source is kept there, so it carries over to the next run.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    dir: Option<String>,
+    states: Mutex<HashMap<String, State>>,
+}
+
+#[derive(Default)]
+struct State {
+    failures: u32,
+    open_until: Option<SystemTime>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration, dir: Option<String>) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            dir,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the source may be fetched.
+    pub fn allows(&self, url: &str) -> bool {
+        self.allows_at(url, SystemTime::now())
+    }
+
+    /// Records the outcome of a fetch.
+    pub fn record(&self, url: &str, success: bool) {
+        self.record_at(url, success, SystemTime::now())
+    }
+
+    /// Returns all sources that are currently skipped.
+    pub fn tripped(&self) -> Vec<String> {
+        let now = SystemTime::now();
+        self.states
+            .lock()
+            .map(|states| {
+                states
+                    .iter()
+                    .filter(|(_, state)| state.open_until.is_some_and(|until| until > now))
+                    .map(|(url, _)| url.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn allows_at(&self, url: &str, now: SystemTime) -> bool {
+        self.states
+            .lock()
+            .map(|mut states| {
+                self.state(&mut states, url)
+                    .open_until
+                    .is_none_or(|until| until <= now)
+            })
+            .unwrap_or(true)
+    }
+
+    fn record_at(&self, url: &str, success: bool, now: SystemTime) {
+        let Ok(mut states) = self.states.lock() else {
+            return;
+        };
+        if success {
+            let state = states.remove(url).or_else(|| self.load(url));
+            if state.is_some_and(|state| state.failures > 0) {
+                self.store(url, None);
+            }
+            return;
+        }
+
+        let state = self.state(&mut states, url);
+        state.failures += 1;
+        if state.failures >= self.threshold {
+            let exponent = (state.failures - self.threshold).min(16);
+            let cooldown = self
+                .cooldown
+                .saturating_mul(1 << exponent)
+                .min(MAX_COOLDOWN);
+            state.open_until = Some(now + cooldown);
+        }
+        self.store(url, Some(state));
+    }
+
+    /// Returns the state of the source, loading it from the directory the first time.
+    fn state<'a>(&self, states: &'a mut HashMap<String, State>, url: &str) -> &'a mut State {
+        states
+            .entry(url.to_owned())
+            .or_insert_with(|| self.load(url).unwrap_or_default())
+    }
+
+    /// Loads the state of the source kept by an earlier run.
+    ///
+    /// A state file consists of the number of consecutive failures
+    /// and the end of the cooldown in seconds since the Unix epoch, empty if not tripped.
+    fn load(&self, url: &str) -> Option<State> {
+        let data = fs::read_to_string(file(self.dir.as_deref()?, url)).ok()?;
+        let mut lines = data.lines();
+        let failures = lines.next()?.parse().ok()?;
+        let open_until = match lines.next().unwrap_or_default() {
+            "" => None,
+            secs => Some(UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?)),
+        };
+        Some(State {
+            failures,
+            open_until,
+        })
+    }
+
+    /// Keeps the state of the source for the next run, a reset source is removed.
+    fn store(&self, url: &str, state: Option<&State>) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let result = match state {
+            Some(state) => {
+                let open_until = state
+                    .open_until
+                    .and_then(|until| until.duration_since(UNIX_EPOCH).ok())
+                    .map(|until| until.as_secs().to_string())
+                    .unwrap_or_default();
+                fs::create_dir_all(dir).and_then(|_| {
+                    let data = format!("{}\n{}\n", state.failures, open_until);
+                    write_atomic(dir, &name(url), data.as_bytes())
+                })
+            }
+            None => fs::remove_file(file(dir, url)),
+        };
+        if let Err(err) = result {
+            warn!("Failed to keep the circuit breaker state of {url}: {err}");
+        }
+    }
+}
+
+/// Name of the state file of the source.
+fn name(url: &str) -> String {
+    format!("{}.{}", fingerprint(url.as_bytes()), EXTENSION)
+}
+
+/// Path of the state file of the source.
+fn file(dir: &str, url: &str) -> String {
+    format!("{}/{}", dir, name(url))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::CircuitBreaker;
+    use std::time::{Duration, SystemTime};
+
+    const URL: &str = "https://1.example.local";
+
+    #[test]
+    fn test_breaker_trips_and_backs_off() -> Result<(), String> {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(10), None);
+        let now = SystemTime::now();
+
+        breaker.record_at(URL, false, now);
+        assert!(breaker.allows_at(URL, now), "Tripped before threshold!");
+        breaker.record_at(URL, false, now);
+        assert!(!breaker.allows_at(URL, now + Duration::from_secs(9)));
+        assert!(breaker.allows_at(URL, now + Duration::from_secs(10)));
+
+        // The probe fails, so the cooldown doubles.
+        let probe = now + Duration::from_secs(10);
+        breaker.record_at(URL, false, probe);
+        assert!(!breaker.allows_at(URL, probe + Duration::from_secs(19)));
+        assert!(breaker.allows_at(URL, probe + Duration::from_secs(20)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_breaker_resets_on_success() -> Result<(), String> {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(10), None);
+        let now = SystemTime::now();
+
+        breaker.record_at(URL, false, now);
+        assert_eq!(vec![URL.to_owned()], breaker.tripped());
+        breaker.record_at(URL, true, now + Duration::from_secs(10));
+        assert!(breaker.allows_at(URL, now));
+        assert!(breaker.tripped().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_breaker_persists() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap().to_owned();
+        let breaker = || CircuitBreaker::new(1, Duration::from_secs(60), Some(dir.clone()));
+        let now = SystemTime::now();
+
+        breaker().record_at(URL, false, now);
+        // A later run still skips the source until the cooldown passed.
+        assert!(!breaker().allows_at(URL, now + Duration::from_secs(59)));
+        assert!(breaker().allows_at(URL, now + Duration::from_secs(60)));
+
+        breaker().record_at(URL, true, now + Duration::from_secs(60));
+        assert!(breaker().allows_at(URL, now));
+        assert_eq!(0, std::fs::read_dir(&dir).unwrap().count());
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use crate::aggregate::lists::Parsed;
+use crate::store::{fingerprint, write_atomic};
+use std::fs;
+use std::io;
+
+/// Version of the cached parse results, part of their hash so results of older parsers are not used.
+pub const VERSION: u32 = 2;
+/// Prefix of a cached exception.
+const EXCEPTION: &str = "@@";
+/// Extension of the cache files, so they don't collide with the ones of the HTTP cache.
+const EXTENSION: &str = "parsed";
+
+/// Name of the cache file of the source.
+fn file(url: &str) -> String {
+    format!("{}.{}", fingerprint(url.as_bytes()), EXTENSION)
+}
+
+/// Loads the parsed domains of the source if they were parsed from a body with the given hash.
+///
+/// A cache file consists of the hash of the body the domains were parsed from,
+/// the number of valid entries before deduplication and then one domain or `@@` exception per line.
+pub fn load(dir: &str, url: &str, hash: &str) -> Option<Parsed> {
+    let data = fs::read_to_string(format!("{}/{}", dir, file(url))).ok()?;
+    let mut lines = data.lines();
+    if lines.next()? != hash {
+        return None;
+    }
+    let mut parsed = Parsed {
+        entries: lines.next()?.parse().ok()?,
+        ..Default::default()
+    };
+    for line in lines {
+        match line.strip_prefix(EXCEPTION) {
+            Some(exception) => parsed.exceptions.insert(exception.to_owned()),
+            None => parsed.domains.insert(line.to_owned()),
+        };
+    }
+    Some(parsed)
+}
+
+/// Stores the parsed domains of the source, replacing the ones of a previous body.
+///
+/// The file is replaced atomically, so a concurrent [load] never sees a partially written file.
+///
+/// # Errors
+/// - If the cache directory or file could not be written.
+pub fn save(dir: &str, url: &str, hash: &str, parsed: &Parsed) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut data = format!("{}\n{}\n", hash, parsed.entries);
+    for domain in &parsed.domains {
+        data.push_str(domain);
+        data.push('\n');
+    }
+    for exception in &parsed.exceptions {
+        data.push_str(EXCEPTION);
+        data.push_str(exception);
+        data.push('\n');
+    }
+    write_atomic(dir, &file(url), data.as_bytes())
+}
@@ -0,0 +1,140 @@
+use crate::aggregate::validation;
+use hickory_resolver::proto::rr::RecordType;
+use hickory_resolver::Resolver;
+use log::warn;
+use std::collections::HashSet;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Maximum number of CNAMEs followed for a single domain.
+const MAX_CHAIN: usize = 8;
+/// CNAME lookups per second if no limit is configured.
+const DEFAULT_RATE_LIMIT: u32 = 20;
+
+/// Spaces out the CNAME lookups of all addlists, so they don't exceed the rate limit together.
+pub struct RateLimiter {
+    delay: Duration,
+    /// Earliest time of the next lookup.
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Allows the given number of lookups per second, [DEFAULT_RATE_LIMIT] if none is given.
+    pub fn new(rate_limit: Option<NonZeroU32>) -> RateLimiter {
+        RateLimiter {
+            delay: Duration::from_secs(1) / rate_limit.map_or(DEFAULT_RATE_LIMIT, NonZeroU32::get),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks until the next lookup is allowed.
+    fn wait(&self) {
+        let slot = match self.next.lock() {
+            Ok(mut next) => {
+                let slot = (*next).max(Instant::now());
+                *next = slot + self.delay;
+                slot
+            }
+            Err(_) => Instant::now() + self.delay,
+        };
+        thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Adds the valid targets of the CNAME chain of each domain.
+///
+/// The lookups are spaced out by the rate limiter, which is shared by all addlists.
+/// If the system resolver is unavailable the domains are returned unchanged.
+pub fn expand(domains: HashSet<String>, limiter: &RateLimiter) -> HashSet<String> {
+    let resolver = match Resolver::from_system_conf() {
+        Ok(resolver) => resolver,
+        Err(err) => {
+            warn!("Could not create resolver to expand CNAMEs: {:?}", err);
+            return domains;
+        }
+    };
+
+    expand_with(domains, |domain| {
+        limiter.wait();
+        resolver
+            .lookup(domain, RecordType::CNAME)
+            .ok()?
+            .record_iter()
+            .find_map(|record| record.data()?.as_cname().map(|cname| cname.to_utf8()))
+    })
+}
+
+/// Follows the CNAME chains with the given lookup function.
+///
+/// A chain ends at an invalid or already known domain, which also prevents loops.
+fn expand_with<F>(domains: HashSet<String>, mut lookup: F) -> HashSet<String>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut expanded = domains.clone();
+    for domain in domains {
+        let mut current = domain;
+        for _ in 0..MAX_CHAIN {
+            match lookup(&current).and_then(|target| validation::validate(&target)) {
+                Some(target) if expanded.insert(target.clone()) => current = target,
+                _ => break,
+            }
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::collections::{HashMap, HashSet};
+    use std::num::NonZeroU32;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_rate_limiter_shared() -> Result<(), String> {
+        let limiter = Arc::new(RateLimiter::new(NonZeroU32::new(50)));
+        let start = Instant::now();
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                thread::spawn(move || (0..5).for_each(|_| limiter.wait()))
+            })
+            .collect();
+        for worker in workers {
+            worker.join().map_err(|_| "Worker panicked")?;
+        }
+
+        // 20 lookups at 50 per second take at least 19 delays of 20ms, regardless of the workers.
+        assert!(start.elapsed() >= Duration::from_millis(380));
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_chain() -> Result<(), String> {
+        let cnames = HashMap::from([
+            ("shop.example.com", "shop.cdn.net."),
+            ("shop.cdn.net", "tracker.org."),
+            ("tracker.org", "shop.example.com."),
+            ("docs.rs", "127.0.0.1"),
+        ]);
+        let domains = HashSet::from(["shop.example.com".to_owned(), "docs.rs".to_owned()]);
+
+        let have = super::expand_with(domains, |domain| {
+            cnames.get(domain).map(|target| target.to_string())
+        });
+        let want = HashSet::from([
+            "shop.example.com".to_owned(),
+            "shop.cdn.net".to_owned(),
+            "tracker.org".to_owned(),
+            "docs.rs".to_owned(),
+        ]);
+        assert_eq!(want, have);
+        Ok(())
+    }
+}
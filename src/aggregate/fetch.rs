@@ -0,0 +1,1811 @@
+use crate::aggregate::breaker::CircuitBreaker;
+use crate::aggregate::cname::RateLimiter;
+use crate::aggregate::http_cache::{self, Cached};
+use crate::aggregate::lists::COMMENT;
+use crate::aggregate::robots::{Robots, USER_AGENT};
+use crate::config::{Config, TlsVersion};
+use crate::events::{Event, Events};
+use crate::thread::StopSignal;
+use anyhow::Error;
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use itertools::Itertools;
+use log::{debug, info, warn};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+};
+use reqwest::redirect::Policy;
+use reqwest::{tls, Certificate, Url};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Backoff before the first retry in milliseconds if none is configured.
+const DEFAULT_RETRY_BACKOFF: u64 = 500;
+/// Upper bound of the backoff before a retry.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5 * 60);
+/// Cooldown of a tripped source if none is configured.
+const DEFAULT_BREAKER_COOLDOWN: u64 = 60;
+/// Scheme of sources whose content is defined in the config.
+const INLINE: &str = "inline://";
+/// Scheme of sources read from the local file system.
+const FILE: &str = "file://";
+/// Why a remote source is not fetched in [Config].offline mode.
+const SKIPPED_OFFLINE: &str = "skipped offline";
+/// Bytes every gzip stream starts with.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Remaining references of a shared source and its data once it was fetched.
+type Shared = (usize, Arc<OnceLock<Option<String>>>);
+
+/// Fetches sources.
+///
+/// A Fetcher is shared by all addlists of a run, so state like the circuit breaker is kept between them.
+pub struct Fetcher {
+    client: Result<Client, String>,
+    /// Clients of sources with a pinned certificate.
+    pinned: HashMap<String, Result<Client, String>>,
+    /// Sources whose body may not contain any entries.
+    allow_empty: HashSet<String>,
+    /// Content of the inline sources.
+    inline: HashMap<String, String>,
+    /// Accepted media types by source, [Config].content_types otherwise.
+    content_types: HashMap<String, HashSet<String>>,
+    default_content_types: Option<HashSet<String>>,
+    /// Timeouts of sources that differ from [Config].timeout.
+    timeouts: HashMap<String, Duration>,
+    /// Headers of sources that send more than [Config].headers.
+    headers: HashMap<String, Result<HeaderMap, String>>,
+    /// robots.txt rules by origin, if they are respected.
+    robots: Option<Mutex<HashMap<String, Arc<Robots>>>>,
+    breaker: Option<CircuitBreaker>,
+    events: Arc<Events>,
+    /// Directory in which the bodies of sources are cached for conditional requests.
+    http_cache: Option<String>,
+    /// Failure of the last request by source.
+    failures: Mutex<HashMap<String, Failure>>,
+    /// Decoded body length of the last fetch or why it failed by source.
+    outcomes: Mutex<HashMap<String, Result<usize, &'static str>>>,
+    /// Sources referenced more than once by the config with their remaining references.
+    /// Their data is kept until the last reference fetched it, so they are requested only once.
+    shared: Mutex<HashMap<String, Shared>>,
+    /// Retries of a request that failed with a network error or a server error.
+    retries: u8,
+    /// Backoff before the first retry, doubled for every further retry.
+    retry_backoff: Duration,
+    /// Whether an interrupted body is used up to its last complete line.
+    accept_partial: bool,
+    /// `Last-Modified` header of the last response by source.
+    last_modified: Mutex<HashMap<String, String>>,
+    /// Whether remote sources are skipped.
+    offline: bool,
+    /// Remote sources skipped in offline mode, which neither count as fetched nor as failed.
+    skipped: Mutex<HashSet<String>>,
+    /// Once stopped, no further source is fetched.
+    stop: StopSignal,
+    /// Rate limit of the CNAME lookups of all addlists, see [Config].cname_rate_limit.
+    cname_limiter: RateLimiter,
+}
+
+/// Why the request of a source failed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Failure {
+    /// The server answered with another status than `200 OK`.
+    Status(u16),
+    /// No response was received.
+    Request,
+    /// The response was served with a media type that is not accepted, like an HTML error page.
+    ContentType,
+    /// The body was compressed but could not be decompressed.
+    Decompress,
+}
+
+impl Failure {
+    /// Whether the source is likely removed from the server and a candidate to be removed from the config.
+    pub fn is_dead(&self) -> bool {
+        matches!(self, Failure::Status(404 | 410))
+    }
+
+    /// Whether a retry may succeed, which is the case for network and server errors.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Failure::Request | Failure::Status(500..=599))
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Status(status) => write!(f, "status {status}"),
+            Failure::Request => f.write_str("no response"),
+            Failure::ContentType => f.write_str("unexpected content type"),
+            Failure::Decompress => f.write_str("invalid compressed body"),
+        }
+    }
+}
+
+/// Response of a request whose body was not read yet.
+enum Sent {
+    /// The server confirmed that the cached body is still current.
+    Unchanged(String),
+    Received {
+        response: Box<Response>,
+        etag: Option<String>,
+        modified: Option<String>,
+        /// Whether the body is deflate compressed.
+        deflated: bool,
+    },
+}
+
+/// Lines of a body that is read while it is received.
+struct Lines<R> {
+    reader: R,
+    /// Bytes of the lines read so far.
+    bytes: usize,
+    /// Whether a line with an entry was read.
+    entries: bool,
+    /// Whether the whole body was read.
+    done: bool,
+    /// Why the body could not be read to its end.
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead> Lines<R> {
+    fn new(reader: R) -> Lines<R> {
+        Lines {
+            reader,
+            bytes: 0,
+            entries: false,
+            done: false,
+            error: None,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.done || self.error.is_some() {
+            return None;
+        }
+        let mut line = Vec::new();
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(read) => {
+                self.bytes += read;
+                let line = String::from_utf8_lossy(&line);
+                let line = line.strip_suffix('\n').unwrap_or(&line);
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                self.entries |= !is_empty(line);
+                Some(line.to_owned())
+            }
+            Err(err) => {
+                self.error = Some(err);
+                None
+            }
+        }
+    }
+}
+
+impl Fetcher {
+    pub fn new(config: &Config) -> Fetcher {
+        let breaker = config.breaker_threshold.map(|threshold| {
+            CircuitBreaker::new(
+                threshold.get(),
+                Duration::from_secs(
+                    config
+                        .breaker_cooldown_secs
+                        .map_or(DEFAULT_BREAKER_COOLDOWN, |cooldown| cooldown.get()),
+                ),
+                config.breaker_cache.clone(),
+            )
+        });
+        let pinned = config
+            .sources
+            .iter()
+            .filter_map(|(url, options)| {
+                let cert = options.pinned_cert.as_deref()?;
+                let client = client(config, Some(cert))
+                    .map_err(|err| format!("Failed to use pinned certificate {cert}: {err}"));
+                Some((url.clone(), client))
+            })
+            .collect();
+        let allow_empty = config
+            .sources
+            .iter()
+            .filter(|(_, options)| options.allow_empty)
+            .map(|(url, _)| url.clone())
+            .collect();
+        let inline = config
+            .sources
+            .iter()
+            .filter(|(url, _)| url.starts_with(INLINE))
+            .filter_map(|(url, options)| Some((url.clone(), options.data.clone()?)))
+            .collect();
+        let content_types = config
+            .sources
+            .iter()
+            .filter_map(|(url, options)| Some((url.clone(), options.content_types.clone()?)))
+            .collect();
+        let headers = config
+            .sources
+            .iter()
+            .filter_map(|(url, options)| {
+                let headers = header_map(options.headers.as_ref()?)
+                    .map_err(|err| format!("Invalid headers of {url}: {err}"));
+                Some((url.clone(), headers))
+            })
+            .collect();
+        let timeouts = config
+            .sources
+            .iter()
+            .filter_map(|(url, options)| {
+                Some((url.clone(), Duration::from_millis(options.timeout?.get())))
+            })
+            .collect();
+        let mut references: HashMap<&String, usize> = HashMap::new();
+        for url in config
+            .whitelist
+            .iter()
+            .flatten()
+            .chain(config.addlist.values().flat_map(|sources| {
+                sources
+                    .addlist
+                    .iter()
+                    .chain(sources.whitelist.iter().flatten())
+                    .chain(sources.blacklist.iter().flatten())
+            }))
+        {
+            *references.entry(url).or_default() += 1;
+        }
+        let shared = references
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(url, count)| (url.clone(), (count, Arc::default())))
+            .collect();
+        Fetcher {
+            client: client(config, None).map_err(|err| err.to_string()),
+            pinned,
+            allow_empty,
+            inline,
+            content_types,
+            default_content_types: config.content_types.clone(),
+            timeouts,
+            headers,
+            robots: config.respect_robots.then(Mutex::default),
+            breaker,
+            events: Arc::default(),
+            http_cache: config.http_cache.clone(),
+            failures: Mutex::default(),
+            outcomes: Mutex::default(),
+            shared: Mutex::new(shared),
+            retries: config.retries.unwrap_or_default(),
+            retry_backoff: Duration::from_millis(
+                config
+                    .retry_backoff_ms
+                    .map_or(DEFAULT_RETRY_BACKOFF, NonZeroU64::get),
+            ),
+            accept_partial: config.accept_partial,
+            last_modified: Mutex::default(),
+            offline: config.offline,
+            skipped: Mutex::default(),
+            stop: StopSignal::new(),
+            cname_limiter: RateLimiter::new(config.cname_rate_limit),
+        }
+    }
+
+    /// Fetches raw domain data
+    ///
+    /// Network and server errors are retried up to [Config].retries times with exponential backoff.
+    /// A body without any entries counts as a failure, unless the source allows it to be empty.
+    /// Inline sources are never fetched, their content is taken from the config.
+    /// Local sources, `file://` URLs or absolute paths, are read from the file system.
+    /// In [Config].offline mode all other sources are skipped without being requested.
+    /// A response with a media type the source does not accept counts as a failure.
+    ///
+    /// A source referenced by several addlists is requested once for all of them,
+    /// concurrent calls wait for the first one to finish. Its data is dropped after the last
+    /// reference got it. Each reference still counts for the circuit breaker, as if it was fetched.
+    ///
+    /// Once a stop was requested no source is fetched anymore.
+    pub fn fetch(&self, url: &str) -> Option<String> {
+        if self.stop.is_stopped() {
+            debug!("Skipped {url}: stop requested.");
+            return None;
+        }
+        let cell = match self.shared.lock() {
+            Ok(mut shared) => match shared.get_mut(url) {
+                Some((remaining, cell)) => {
+                    let cell = Arc::clone(cell);
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        shared.remove(url);
+                    }
+                    cell
+                }
+                None => return self.fetch_uncached(url),
+            },
+            Err(_) => return self.fetch_uncached(url),
+        };
+        let mut fetched = false;
+        let data = cell
+            .get_or_init(|| {
+                fetched = true;
+                self.fetch_uncached(url)
+            })
+            .clone();
+        if let (false, Some(breaker)) = (fetched, &self.breaker) {
+            breaker.record(url, self.is_healthy(url, data.as_deref()));
+        }
+        data
+    }
+
+    /// Whether the data counts as success for the circuit breaker, which a body without entries
+    /// does not, unless the source allows it to be empty.
+    fn is_healthy(&self, url: &str, data: Option<&str>) -> bool {
+        data.is_some_and(|data| !is_empty(data) || self.allow_empty.contains(url))
+    }
+
+    /// Fetches raw domain data, regardless of whether it was fetched before.
+    fn fetch_uncached(&self, url: &str) -> Option<String> {
+        let data = self.fetch_source(url);
+        self.record_outcome(
+            url,
+            data.as_ref().map(String::len).map_err(|reason| *reason),
+        );
+        data.ok()
+    }
+
+    /// Fetches the source like [Fetcher::fetch], but passes its body to `read` line by line while it is received.
+    ///
+    /// Once `read` stops taking lines, the rest of the body is not received at all.
+    /// Inline and shared sources as well as sources whose body is kept as a whole,
+    /// due to [Config].http_cache or [Config].accept_partial, are fetched completely first.
+    /// If the body can not be read to its end, the result of `read` is dropped.
+    pub fn fetch_lines<T>(
+        &self,
+        url: &str,
+        read: impl FnOnce(&mut dyn Iterator<Item = String>) -> T,
+    ) -> Option<T> {
+        let shared = self
+            .shared
+            .lock()
+            .map_or(true, |shared| shared.contains_key(url));
+        if shared || url.starts_with(INLINE) || self.http_cache.is_some() || self.accept_partial {
+            return self
+                .fetch(url)
+                .map(|data| read(&mut data.lines().map(str::to_owned)));
+        }
+        if self.stop.is_stopped() {
+            debug!("Skipped {url}: stop requested.");
+            return None;
+        }
+        let read = self.stream_source(url, read);
+        self.record_outcome(
+            url,
+            read.as_ref()
+                .map(|(_, bytes)| *bytes)
+                .map_err(|reason| *reason),
+        );
+        read.ok().map(|(read, _)| read)
+    }
+
+    /// Reads the body of the source with `read` while it is received, see [Fetcher::fetch_lines].
+    ///
+    /// Returns the result of `read` and the bytes it read or why the source could not be read.
+    fn stream_source<T>(
+        &self,
+        url: &str,
+        read: impl FnOnce(&mut dyn Iterator<Item = String>) -> T,
+    ) -> Result<(T, usize), &'static str> {
+        if let Some(path) = local_path(url) {
+            let mut lines = fs::File::open(path)
+                .map(|file| Lines::new(BufReader::new(file)))
+                .map_err(|err| {
+                    warn!("Failed to read {url}: {err}");
+                    "file could not be read"
+                })?;
+            let read = read(&mut lines);
+            return match lines.error {
+                Some(err) => {
+                    warn!("Failed to read {url}: {err}");
+                    Err("file could not be read")
+                }
+                None => Ok((read, lines.bytes)),
+            };
+        }
+        if let Some(reason) = self.skip_reason(url) {
+            return Err(reason);
+        }
+        let content_types = self.content_types(url);
+        let read = self
+            .retried(url, || self.send(url, content_types))
+            .and_then(|sent| {
+                let (reader, compressed) = body_reader(sent)?;
+                let mut lines = Lines::new(reader);
+                let read = read(&mut lines);
+                match lines.error {
+                    Some(err) => {
+                        warn!("Failed to fetch {url}: {err}");
+                        let invalid =
+                            matches!(err.kind(), ErrorKind::InvalidData | ErrorKind::InvalidInput);
+                        Err(if compressed && invalid {
+                            Failure::Decompress
+                        } else {
+                            Failure::Request
+                        })
+                    }
+                    None => Ok((read, lines)),
+                }
+            });
+        self.record_failure(url, read.as_ref().err().copied());
+        let healthy = read.as_ref().is_ok_and(|(_, lines)| {
+            // A body that was not read to its end may still contain entries.
+            lines.entries || !lines.done || self.allow_empty.contains(url)
+        });
+        self.record_health(url, read.is_ok(), healthy);
+        read.map(|(read, lines)| (read, lines.bytes))
+            .map_err(reason)
+    }
+
+    /// Keeps the fetched bytes or why the fetch failed as the outcome of the source and emits it.
+    ///
+    /// A source skipped in offline mode is kept as skipped instead.
+    fn record_outcome(&self, url: &str, outcome: Result<usize, &'static str>) {
+        if outcome == Err(SKIPPED_OFFLINE) {
+            if let Ok(mut skipped) = self.skipped.lock() {
+                skipped.insert(url.to_owned());
+            }
+            self.events.emit(Event::SourceSkipped { url });
+            return;
+        }
+        if let Ok(mut outcomes) = self.outcomes.lock() {
+            outcomes.insert(url.to_owned(), outcome);
+        }
+        match outcome {
+            Ok(bytes) => self.events.emit(Event::SourceFetched { url, bytes }),
+            Err(reason) => self.events.emit(Event::SourceFailed { url, reason }),
+        }
+    }
+
+    /// Fetches raw domain data or explains why it could not be fetched.
+    fn fetch_source(&self, url: &str) -> Result<String, &'static str> {
+        if url.starts_with(INLINE) {
+            let data = self.inline.get(url).cloned();
+            if data.is_none() {
+                info!("Inline source {url} has no data.");
+            }
+            return data.ok_or("inline source has no data");
+        }
+        if let Some(path) = local_path(url) {
+            return fs::read_to_string(path).map_err(|err| {
+                warn!("Failed to read {url}: {err}");
+                "file could not be read"
+            });
+        }
+        if let Some(reason) = self.skip_reason(url) {
+            return Err(reason);
+        }
+
+        let content_types = self.content_types(url);
+        let data = self.retried(url, || self.request(url, content_types));
+        self.record_failure(url, data.as_ref().err().copied());
+        let healthy = self.is_healthy(url, data.as_deref().ok());
+        self.record_health(url, data.is_ok(), healthy);
+        data.map_err(reason)
+    }
+
+    /// Why the remote source is skipped instead of requested, if it is.
+    fn skip_reason(&self, url: &str) -> Option<&'static str> {
+        if self.offline {
+            info!("Skipped {url}: offline.");
+            return Some(SKIPPED_OFFLINE);
+        }
+        if !self.robots_allow(url) {
+            info!("Skipped {url}: disallowed by robots.txt.");
+            return Some("disallowed by robots.txt");
+        }
+        if let Some(breaker) = &self.breaker {
+            if !breaker.allows(url) {
+                info!("Skipped {url}: source is tripped.");
+                return Some("source is tripped");
+            }
+        }
+        None
+    }
+
+    /// Accepted media types of the source.
+    fn content_types(&self, url: &str) -> Option<&HashSet<String>> {
+        self.content_types
+            .get(url)
+            .or(self.default_content_types.as_ref())
+    }
+
+    /// Runs the request and retries it up to [Config].retries times while it fails transiently.
+    fn retried<T>(
+        &self,
+        url: &str,
+        request: impl Fn() -> Result<T, Failure>,
+    ) -> Result<T, Failure> {
+        let mut result = request();
+        for attempt in 0..self.retries {
+            match result {
+                Err(failure) if failure.is_transient() => {
+                    let backoff = self.backoff(attempt);
+                    warn!("Fetching {url} failed with {failure}, retrying in {backoff:?}.");
+                    thread::sleep(backoff);
+                    result = request();
+                }
+                _ => break,
+            }
+        }
+        result
+    }
+
+    /// Keeps the failure of the last request of the source, see [Fetcher::failures].
+    fn record_failure(&self, url: &str, failure: Option<Failure>) {
+        if let Ok(mut failures) = self.failures.lock() {
+            match failure {
+                None => failures.remove(url),
+                Some(failure) => failures.insert(url.to_owned(), failure),
+            };
+        }
+    }
+
+    /// Records the fetch of the source for the circuit breaker.
+    fn record_health(&self, url: &str, fetched: bool, healthy: bool) {
+        if fetched && !healthy {
+            warn!("Source {url} does not contain any entries.");
+        }
+        if let Some(breaker) = &self.breaker {
+            breaker.record(url, healthy);
+        }
+    }
+
+    /// Backoff before the retry after the given attempt, doubled for every attempt
+    /// up to [MAX_RETRY_BACKOFF].
+    fn backoff(&self, attempt: u8) -> Duration {
+        self.retry_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.into()))
+            .min(MAX_RETRY_BACKOFF)
+    }
+
+    /// Emits the events of all fetched sources.
+    pub fn with_events(self, events: Arc<Events>) -> Fetcher {
+        Fetcher { events, ..self }
+    }
+
+    /// Stops fetching once the given signal is stopped instead of the one created with the Fetcher.
+    pub fn with_stop(self, stop: StopSignal) -> Fetcher {
+        Fetcher { stop, ..self }
+    }
+
+    /// Returns all sources whose last request failed, sorted.
+    pub fn failures(&self) -> Vec<(String, Failure)> {
+        self.failures
+            .lock()
+            .map(|failures| {
+                failures
+                    .iter()
+                    .map(|(url, failure)| (url.clone(), *failure))
+                    .sorted()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Number of sources whose last fetch succeeded and number of sources whose last fetch failed.
+    pub fn outcomes(&self) -> (usize, usize) {
+        self.outcomes
+            .lock()
+            .map(|outcomes| {
+                let fetched = outcomes.values().filter(|outcome| outcome.is_ok()).count();
+                (fetched, outcomes.len() - fetched)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Decoded body length or why the fetch failed of the last fetch of every source, sorted.
+    pub fn source_outcomes(&self) -> Vec<(String, Result<usize, &'static str>)> {
+        self.outcomes
+            .lock()
+            .map(|outcomes| {
+                outcomes
+                    .iter()
+                    .map(|(url, outcome)| (url.clone(), *outcome))
+                    .sorted()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Rate limiter of the CNAME lookups, shared by all addlists of the run.
+    pub(crate) fn cname_limiter(&self) -> &RateLimiter {
+        &self.cname_limiter
+    }
+
+    /// Returns all sources skipped in [Config].offline mode, sorted.
+    pub fn skipped(&self) -> Vec<String> {
+        self.skipped
+            .lock()
+            .map(|skipped| skipped.iter().cloned().sorted().collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether the source is skipped in [Config].offline mode, which all sources are that are
+    /// neither local nor inline.
+    pub fn skips_offline(&self, url: &str) -> bool {
+        self.offline && !url.starts_with(INLINE) && local_path(url).is_none()
+    }
+
+    /// `Last-Modified` of the last response of the source, if the server sent one.
+    pub fn last_modified(&self, url: &str) -> Option<String> {
+        self.last_modified.lock().ok()?.get(url).cloned()
+    }
+
+    /// Returns all sources that are currently skipped by the circuit breaker.
+    pub fn tripped(&self) -> Vec<String> {
+        self.breaker
+            .as_ref()
+            .map(CircuitBreaker::tripped)
+            .unwrap_or_default()
+    }
+
+    /// Checks that the source is reachable by fetching its first byte only.
+    ///
+    /// # Errors
+    /// - If the source could not be fetched.
+    pub fn probe(&self, url: &str) -> Result<(), String> {
+        if url.starts_with(INLINE) {
+            return if self.inline.contains_key(url) {
+                Ok(())
+            } else {
+                Err("Inline source has no data".to_owned())
+            };
+        }
+        if let Some(path) = local_path(url) {
+            return fs::File::open(path)
+                .map(|_| ())
+                .map_err(|err| err.to_string());
+        }
+        if self.offline {
+            return Err(SKIPPED_OFFLINE.to_owned());
+        }
+        let client = self.pinned.get(url).unwrap_or(&self.client).as_ref()?;
+        let response = client
+            .get(url)
+            .headers(self.source_headers(url)?)
+            .header(RANGE, "bytes=0-0")
+            .send()
+            .map_err(|err| err.to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("Status {}", response.status()))
+        }
+    }
+
+    /// Whether the robots.txt of the host allows to fetch the source.
+    ///
+    /// The robots.txt is fetched once per host, a missing robots.txt allows everything.
+    fn robots_allow(&self, url: &str) -> bool {
+        let (Some(robots), Ok(url)) = (&self.robots, Url::parse(url)) else {
+            return true;
+        };
+        let origin = url.origin().ascii_serialization();
+        let Ok(mut cache) = robots.lock() else {
+            return true;
+        };
+        let rules = cache
+            .entry(origin.clone())
+            .or_insert_with(|| {
+                let body = self.request(&format!("{origin}/robots.txt"), None).ok();
+                Arc::new(body.as_deref().map(Robots::parse).unwrap_or_default())
+            })
+            .clone();
+        drop(cache);
+        rules.allows(url.path())
+    }
+
+    /// Headers sent only with the source, the ones of [Config].headers are sent by every client.
+    fn source_headers(&self, url: &str) -> Result<HeaderMap, String> {
+        self.headers
+            .get(url)
+            .cloned()
+            .unwrap_or_else(|| Ok(HeaderMap::new()))
+    }
+
+    /// Fetches the body of the url, if it is served with one of the content types.
+    fn request(
+        &self,
+        url: &str,
+        content_types: Option<&HashSet<String>>,
+    ) -> Result<String, Failure> {
+        let (response, etag, modified, deflated) = match self.send(url, content_types)? {
+            Sent::Unchanged(body) => return Ok(body),
+            Sent::Received {
+                response,
+                etag,
+                modified,
+                deflated,
+            } => (*response, etag, modified, deflated),
+        };
+        let (body, interrupted) = if self.accept_partial {
+            read_partial(url, response)?
+        } else {
+            let body = response.bytes().map_err(|_| Failure::Request)?;
+            (body.to_vec(), false)
+        };
+        let body = decode(url, &body, deflated, interrupted)?;
+        if let Some(dir) = &self.http_cache {
+            // An interrupted body must not be reused for a later unchanged response.
+            if !interrupted && (etag.is_some() || modified.is_some()) {
+                let cached = Cached {
+                    etag,
+                    last_modified: modified,
+                    body,
+                };
+                if let Err(err) = http_cache::save(dir, url, &cached) {
+                    warn!("Failed to cache the body of {url}: {err}");
+                }
+                return Ok(cached.body);
+            }
+        }
+        Ok(body)
+    }
+
+    /// Requests the url without reading the body, if it is served with one of the content types.
+    fn send(&self, url: &str, content_types: Option<&HashSet<String>>) -> Result<Sent, Failure> {
+        let pinned = self.pinned.get(url);
+        let client = match pinned.unwrap_or(&self.client) {
+            Ok(client) => client,
+            Err(err) => {
+                warn!("Failed to fetch {url}: {err}");
+                return Err(Failure::Request);
+            }
+        };
+        let headers = match self.source_headers(url) {
+            Ok(headers) => headers,
+            Err(err) => {
+                warn!("Failed to fetch {url}: {err}");
+                return Err(Failure::Request);
+            }
+        };
+        let mut request = client.get(url).headers(headers);
+        if let Some(timeout) = self.timeouts.get(url) {
+            request = request.timeout(*timeout);
+        }
+        let cached = self
+            .http_cache
+            .as_deref()
+            .and_then(|dir| http_cache::load(dir, url));
+        if let Some(cached) = &cached {
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, modified);
+            }
+        }
+        let response = match request.send() {
+            Ok(response) => response,
+            Err(err) if pinned.is_some() => {
+                warn!("Failed to fetch {url} with pinned certificate: {err}");
+                return Err(Failure::Request);
+            }
+            Err(_) => return Err(Failure::Request),
+        };
+        if let (304, Some(cached)) = (response.status().as_u16(), cached) {
+            if let (Some(modified), Ok(mut last_modified)) =
+                (&cached.last_modified, self.last_modified.lock())
+            {
+                last_modified.insert(url.to_owned(), modified.clone());
+            }
+            return Ok(Sent::Unchanged(cached.body));
+        }
+        if response.status() == 200 {
+            let media_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| {
+                    value
+                        .split(';')
+                        .next()
+                        .unwrap_or_default()
+                        .trim()
+                        .to_owned()
+                });
+            if let (Some(accepted), Some(media_type)) = (content_types, media_type) {
+                if !accepted
+                    .iter()
+                    .any(|accepted| accepted.eq_ignore_ascii_case(&media_type))
+                {
+                    warn!("Failed to fetch {url}: unexpected content type {media_type}.");
+                    return Err(Failure::ContentType);
+                }
+            }
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned)
+            };
+            let modified = header(LAST_MODIFIED);
+            let etag = header(ETAG);
+            if let (Some(modified), Ok(mut last_modified)) = (&modified, self.last_modified.lock())
+            {
+                last_modified.insert(url.to_owned(), modified.clone());
+            }
+            let deflated = response
+                .headers()
+                .get(CONTENT_ENCODING)
+                .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"deflate"));
+            return Ok(Sent::Received {
+                response: Box::new(response),
+                etag,
+                modified,
+                deflated,
+            });
+        }
+        Err(Failure::Status(response.status().as_u16()))
+    }
+}
+
+/// Reads the body, keeping the bytes received before the transfer was interrupted.
+///
+/// Returns whether the transfer was interrupted, so [decode] drops the incomplete last line.
+fn read_partial(url: &str, mut response: Response) -> Result<(Vec<u8>, bool), Failure> {
+    let mut body = Vec::new();
+    match response.read_to_end(&mut body) {
+        Ok(_) => Ok((body, false)),
+        Err(_) if body.is_empty() => Err(Failure::Request),
+        Err(err) => {
+            warn!("Fetching {url} was interrupted ({err}), using the complete lines only.");
+            Ok((body, true))
+        }
+    }
+}
+
+/// Reader of the body of a response, decompressing it if it is gzip or deflate compressed like [decode].
+///
+/// Returns whether the body is compressed.
+fn body_reader(sent: Sent) -> Result<(Box<dyn BufRead>, bool), Failure> {
+    let (response, deflated) = match sent {
+        Sent::Unchanged(body) => return Ok((Box::new(io::Cursor::new(body.into_bytes())), false)),
+        Sent::Received {
+            response, deflated, ..
+        } => (response, deflated),
+    };
+    let mut reader = BufReader::new(response);
+    let gzipped = reader
+        .fill_buf()
+        .map_err(|_| Failure::Request)?
+        .starts_with(&GZIP_MAGIC);
+    Ok(if gzipped {
+        (Box::new(BufReader::new(MultiGzDecoder::new(reader))), true)
+    } else if deflated {
+        (Box::new(BufReader::new(ZlibDecoder::new(reader))), true)
+    } else {
+        (Box::new(reader), false)
+    })
+}
+
+/// Why a source could not be fetched, for its outcome.
+fn reason(failure: Failure) -> &'static str {
+    match failure {
+        Failure::ContentType => "unexpected content type",
+        _ => "request failed",
+    }
+}
+
+/// Whether the entry names a source, which is a local path or a URL with a scheme sources are fetched with.
+///
+/// Anything else is no source, even if it parses as URL like `docs.rs:443` or `name:1`.
+pub fn is_source(entry: &str) -> bool {
+    local_path(entry).is_some()
+        || entry.starts_with(INLINE)
+        || Url::parse(entry).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Path of a source that is read from the local file system,
+/// which is either a `file://` URL or an absolute path.
+pub fn local_path(url: &str) -> Option<PathBuf> {
+    if url.starts_with(FILE) {
+        Url::parse(url).ok()?.to_file_path().ok()
+    } else if url.starts_with('/') {
+        Some(PathBuf::from(url))
+    } else {
+        None
+    }
+}
+
+/// Decodes the body as text, decompressing it first if it is gzip or deflate compressed.
+///
+/// Gzip is detected by its magic bytes, so lists served as `.gz` files are decompressed
+/// just like responses the client did not decompress itself.
+/// The body of an interrupted transfer is decoded as far as possible and cut after its last
+/// complete line.
+fn decode(url: &str, body: &[u8], deflated: bool, interrupted: bool) -> Result<String, Failure> {
+    let mut text = Vec::new();
+    let result = if body.starts_with(&GZIP_MAGIC) {
+        MultiGzDecoder::new(body).read_to_end(&mut text)
+    } else if deflated {
+        ZlibDecoder::new(body).read_to_end(&mut text)
+    } else {
+        text.extend_from_slice(body);
+        Ok(body.len())
+    };
+    match result {
+        // A compressed stream that was cut off ends early, its decompressed lines are still usable.
+        Err(_) if interrupted => {}
+        Err(err) => {
+            warn!("Failed to decompress {url}: {err}");
+            return Err(Failure::Decompress);
+        }
+        Ok(_) => {}
+    }
+    if interrupted {
+        // The last line may be incomplete.
+        let end = text
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |index| index + 1);
+        text.truncate(end);
+        if text.is_empty() {
+            return Err(Failure::Request);
+        }
+    }
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
+/// Whether the body only consists of blank lines and comments.
+fn is_empty(body: &str) -> bool {
+    body.lines().all(|line| {
+        line.find(COMMENT)
+            .map_or(line, |index| &line[..index])
+            .trim()
+            .is_empty()
+    })
+}
+
+/// Creates a client that only trusts the pinned certificate if given.
+///
+/// The client only decompresses gzip encoded responses if [Config].accept_compressed is set.
+/// Requests time out after [Config].timeout, otherwise after the default of reqwest.
+/// A configured [Config].user_agent is sent instead of the one of this program.
+/// At most [Config].max_redirects redirects are followed, otherwise the default of reqwest.
+pub fn client(config: &Config, pinned_cert: Option<&str>) -> Result<Client, Error> {
+    let mut builder = Client::builder().gzip(config.accept_compressed);
+    if let Some(timeout) = config.timeout {
+        builder = builder.timeout(Duration::from_millis(timeout.get()));
+    }
+    if let Some(max) = config.max_redirects {
+        builder = builder.redirect(match max {
+            0 => Policy::none(),
+            // The limit of reqwest counts the initial request as well.
+            max => Policy::limited(max.saturating_add(1)),
+        });
+    }
+    if let Some(user_agent) = &config.user_agent {
+        builder = builder.user_agent(user_agent);
+    } else if config.respect_robots {
+        builder = builder.user_agent(USER_AGENT);
+    }
+    if let Some(version) = config.min_tls_version {
+        builder = builder.min_tls_version(match version {
+            TlsVersion::Tls10 => tls::Version::TLS_1_0,
+            TlsVersion::Tls11 => tls::Version::TLS_1_1,
+            TlsVersion::Tls12 => tls::Version::TLS_1_2,
+            TlsVersion::Tls13 => tls::Version::TLS_1_3,
+        });
+    }
+    if let Some(headers) = &config.headers {
+        builder = builder.default_headers(header_map(headers)?);
+    }
+    if let Some(path) = pinned_cert {
+        let cert = Certificate::from_pem(&fs::read(path)?)?;
+        builder = builder
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(cert);
+    }
+    Ok(builder.build()?)
+}
+
+/// Converts configured headers, which fails for invalid names or values.
+fn header_map(headers: &HashMap<String, String>) -> Result<HeaderMap, Error> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            Ok((
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{Failure, Fetcher};
+    use crate::config::{Config, SourceOptions};
+    use crate::data::AddlistSources;
+    use crate::thread::StopSignal;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use mockito::mock;
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::ssl::{SslAcceptor, SslMethod};
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::num::{NonZeroU32, NonZeroU64};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_fetch_tripped() -> Result<(), String> {
+        let mock = mock("GET", "/tripped").with_status(503).expect(2).create();
+        let url = mockito::server_url() + "/tripped";
+        let fetcher = Fetcher::new(&Config {
+            breaker_threshold: NonZeroU32::new(2),
+            ..Default::default()
+        });
+
+        for _ in 0..3 {
+            assert_eq!(None, fetcher.fetch(&url));
+        }
+
+        mock.assert();
+        assert_eq!(vec![url], fetcher.tripped());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_shared() -> Result<(), String> {
+        let available = mock("GET", "/shared-available")
+            .with_status(200)
+            .with_body("docs.rs")
+            .expect(2)
+            .create();
+        let unavailable = mock("GET", "/shared-unavailable")
+            .with_status(503)
+            .expect(1)
+            .create();
+        let available_url = mockito::server_url() + "/shared-available";
+        let unavailable_url = mockito::server_url() + "/shared-unavailable";
+        let sources = || AddlistSources {
+            addlist: HashSet::from([available_url.clone(), unavailable_url.clone()]),
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new(&Config {
+            breaker_threshold: NonZeroU32::new(2),
+            addlist: HashMap::from([
+                ("Ads".to_owned(), sources()),
+                ("Trackers".to_owned(), sources()),
+            ]),
+            ..Default::default()
+        });
+
+        for _ in 0..2 {
+            assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(&available_url));
+            assert_eq!(None, fetcher.fetch(&unavailable_url));
+        }
+        // Both references of the unavailable source count for the circuit breaker.
+        assert_eq!(vec![unavailable_url], fetcher.tripped());
+        // The data was dropped after the last reference got it.
+        assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(&available_url));
+
+        available.assert();
+        unavailable.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_pinned_cert_invalid() -> Result<(), String> {
+        let mock = mock("GET", "/pinned").with_status(200).expect(0).create();
+        let url = mockito::server_url() + "/pinned";
+        let fetcher = Fetcher::new(&Config {
+            sources: HashMap::from([(
+                url.clone(),
+                SourceOptions {
+                    pinned_cert: Some("./does-not-exist.pem".to_owned()),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        });
+
+        assert_eq!(None, fetcher.fetch(&url));
+        mock.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_allow_empty() -> Result<(), String> {
+        let body = "# Maintained by hand.\n\n# Nothing to block right now.\n";
+        let allowed = mock("GET", "/allow-empty")
+            .with_status(200)
+            .with_body(body)
+            .expect(2)
+            .create();
+        let empty = mock("GET", "/empty")
+            .with_status(200)
+            .with_body(body)
+            .expect(1)
+            .create();
+        let allowed_url = mockito::server_url() + "/allow-empty";
+        let empty_url = mockito::server_url() + "/empty";
+        let fetcher = Fetcher::new(&Config {
+            breaker_threshold: NonZeroU32::new(1),
+            sources: HashMap::from([(
+                allowed_url.clone(),
+                SourceOptions {
+                    allow_empty: true,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        });
+
+        for _ in 0..2 {
+            assert_eq!(Some(body.to_owned()), fetcher.fetch(&allowed_url));
+        }
+        assert_eq!(Some(body.to_owned()), fetcher.fetch(&empty_url));
+        assert_eq!(None, fetcher.fetch(&empty_url));
+
+        allowed.assert();
+        empty.assert();
+        assert_eq!(vec![empty_url], fetcher.tripped());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_compressed() -> Result<(), String> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"docs.rs").unwrap();
+        let mock = mock("GET", "/compressed")
+            .match_header("accept-encoding", "gzip")
+            .with_status(200)
+            .with_header("content-encoding", "gzip")
+            .with_body(encoder.finish().unwrap())
+            .create();
+        let url = mockito::server_url() + "/compressed";
+        let fetcher = Fetcher::new(&Config {
+            accept_compressed: true,
+            ..Default::default()
+        });
+
+        assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(&url));
+        mock.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_inline() -> Result<(), String> {
+        let fetcher = Fetcher::new(&Config {
+            sources: HashMap::from([(
+                "inline://demo".to_owned(),
+                SourceOptions {
+                    data: Some("docs.rs\nt.org".to_owned()),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            Some("docs.rs\nt.org".to_owned()),
+            fetcher.fetch("inline://demo")
+        );
+        assert_eq!(Ok(()), fetcher.probe("inline://demo"));
+        assert_eq!(None, fetcher.fetch("inline://missing"));
+        assert!(fetcher.probe("inline://missing").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_respect_robots() -> Result<(), String> {
+        let robots = mock("GET", "/robots.txt")
+            .with_status(200)
+            .with_body("User-agent: *\nDisallow: /robots-private")
+            .expect(1)
+            .create();
+        let private = mock("GET", "/robots-private").expect(0).create();
+        let public = mock("GET", "/robots-public")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+        let fetcher = Fetcher::new(&Config {
+            respect_robots: true,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            None,
+            fetcher.fetch(&(mockito::server_url() + "/robots-private"))
+        );
+        assert_eq!(
+            Some("docs.rs".to_owned()),
+            fetcher.fetch(&(mockito::server_url() + "/robots-public"))
+        );
+
+        robots.assert();
+        private.assert();
+        public.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_failures() -> Result<(), String> {
+        let gone = mock("GET", "/gone").with_status(410).create();
+        let unavailable = mock("GET", "/unavailable").with_status(503).create();
+        let gone_url = mockito::server_url() + "/gone";
+        let unavailable_url = mockito::server_url() + "/unavailable";
+        let fetcher = Fetcher::new(&Config::default());
+
+        assert_eq!(None, fetcher.fetch(&gone_url));
+        assert_eq!(None, fetcher.fetch(&unavailable_url));
+
+        gone.assert();
+        unavailable.assert();
+        let failures = fetcher.failures();
+        assert_eq!(
+            vec![
+                (gone_url, Failure::Status(410)),
+                (unavailable_url, Failure::Status(503))
+            ],
+            failures
+        );
+        assert!(failures[0].1.is_dead());
+        assert!(!failures[1].1.is_dead());
+        assert_eq!((0, 2), fetcher.outcomes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_content_type() -> Result<(), String> {
+        let html = mock("GET", "/soft-404")
+            .with_status(200)
+            .with_header("content-type", "text/html; charset=utf-8")
+            .with_body("<html><body>rust-lang.org</body></html>")
+            .create();
+        let plain = mock("GET", "/content-type")
+            .with_status(200)
+            .with_header("content-type", "text/plain; charset=utf-8")
+            .with_body("docs.rs")
+            .create();
+        let html_url = mockito::server_url() + "/soft-404";
+        let plain_url = mockito::server_url() + "/content-type";
+        let fetcher = Fetcher::new(&Config {
+            content_types: Some(HashSet::from(["text/plain".to_owned()])),
+            ..Default::default()
+        });
+
+        assert_eq!(None, fetcher.fetch(&html_url));
+        assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(&plain_url));
+
+        html.assert();
+        plain.assert();
+        assert_eq!(vec![(html_url, Failure::ContentType)], fetcher.failures());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_headers() -> Result<(), String> {
+        let mocked = mock("GET", "/headers")
+            .match_header("accept", "text/plain")
+            .match_header("referer", "https://lists.example.local/")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+        let url = mockito::server_url() + "/headers";
+        let fetcher = Fetcher::new(&Config {
+            headers: Some(HashMap::from([
+                ("Accept".to_owned(), "text/plain".to_owned()),
+                ("Referer".to_owned(), "https://example.local/".to_owned()),
+            ])),
+            sources: HashMap::from([(
+                url.clone(),
+                SourceOptions {
+                    headers: Some(HashMap::from([(
+                        "Referer".to_owned(),
+                        "https://lists.example.local/".to_owned(),
+                    )])),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        });
+
+        assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(&url));
+        mocked.assert();
+
+        let fetcher = Fetcher::new(&Config {
+            headers: Some(HashMap::from([("Invalid Name".to_owned(), String::new())])),
+            ..Default::default()
+        });
+        assert_eq!(None, fetcher.fetch(&url));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_accept_partial() -> Result<(), String> {
+        // Announces more bytes than are sent before the connection is closed.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/partial", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 1000\r\n\r\ndocs.rs\nt.org\nrust-la",
+                    )
+                    .unwrap();
+            }
+        });
+
+        let fetcher = Fetcher::new(&Config::default());
+        assert_eq!(None, fetcher.fetch(&url));
+        let fetcher = Fetcher::new(&Config {
+            accept_partial: true,
+            ..Default::default()
+        });
+        assert_eq!(Some("docs.rs\nt.org\n".to_owned()), fetcher.fetch(&url));
+        server.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_accept_partial_compressed() -> Result<(), String> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"docs.rs\nt.org").unwrap();
+        let changed = mock("GET", "/partial.gz")
+            .with_status(200)
+            .with_header("etag", "\"v1\"")
+            .with_body(encoder.finish().unwrap())
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let url = mockito::server_url() + "/partial.gz";
+        let config = Config {
+            accept_partial: true,
+            http_cache: Some(dir.path().to_str().unwrap().to_owned()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Some("docs.rs\nt.org".to_owned()),
+            Fetcher::new(&config).fetch(&url)
+        );
+        changed.assert();
+
+        let unchanged = mock("GET", "/partial.gz")
+            .match_header("if-none-match", "\"v1\"")
+            .with_status(304)
+            .create();
+        assert_eq!(
+            Some("docs.rs\nt.org".to_owned()),
+            Fetcher::new(&config).fetch(&url)
+        );
+        unchanged.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_retries() -> Result<(), String> {
+        let unavailable = mock("GET", "/retry").with_status(503).expect(2).create();
+        let available = mock("GET", "/retry")
+            .with_status(200)
+            .with_body("docs.rs")
+            .expect(1)
+            .create();
+        let missing = mock("GET", "/retry-missing")
+            .with_status(404)
+            .expect(1)
+            .create();
+        let fetcher = Fetcher::new(&Config {
+            retries: Some(3),
+            retry_backoff_ms: NonZeroU64::new(1),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            Some("docs.rs".to_owned()),
+            fetcher.fetch(&(mockito::server_url() + "/retry"))
+        );
+        assert_eq!(
+            None,
+            fetcher.fetch(&(mockito::server_url() + "/retry-missing"))
+        );
+
+        unavailable.assert();
+        available.assert();
+        missing.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_backoff() -> Result<(), String> {
+        let fetcher = Fetcher::new(&Config {
+            retry_backoff_ms: NonZeroU64::new(100),
+            ..Default::default()
+        });
+        assert_eq!(Duration::from_millis(100), fetcher.backoff(0));
+        assert_eq!(Duration::from_millis(400), fetcher.backoff(2));
+        assert_eq!(super::MAX_RETRY_BACKOFF, fetcher.backoff(u8::MAX));
+
+        let fetcher = Fetcher::new(&Config {
+            retry_backoff_ms: NonZeroU64::new(u64::MAX),
+            ..Default::default()
+        });
+        assert_eq!(super::MAX_RETRY_BACKOFF, fetcher.backoff(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_gz_file() -> Result<(), String> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(b"0.0.0.0 docs.rs\n0.0.0.0 t.org")
+            .unwrap();
+        let mock = mock("GET", "/hosts.gz")
+            .with_status(200)
+            .with_header("content-type", "application/gzip")
+            .with_body(encoder.finish().unwrap())
+            .create();
+        let url = mockito::server_url() + "/hosts.gz";
+        let fetcher = Fetcher::new(&Config::default());
+
+        let have = crate::aggregate::lists::parse(fetcher.fetch(&url).unwrap());
+
+        mock.assert();
+        assert_eq!(
+            HashSet::from(["docs.rs".to_owned(), "t.org".to_owned()]),
+            have
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_deflate() -> Result<(), String> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"docs.rs").unwrap();
+        let deflated = mock("GET", "/deflate")
+            .with_status(200)
+            .with_header("content-encoding", "deflate")
+            .with_body(encoder.finish().unwrap())
+            .create();
+        let broken = mock("GET", "/deflate-broken")
+            .with_status(200)
+            .with_header("content-encoding", "deflate")
+            .with_body("docs.rs")
+            .create();
+        let fetcher = Fetcher::new(&Config::default());
+
+        assert_eq!(
+            Some("docs.rs".to_owned()),
+            fetcher.fetch(&(mockito::server_url() + "/deflate"))
+        );
+        assert_eq!(
+            None,
+            fetcher.fetch(&(mockito::server_url() + "/deflate-broken"))
+        );
+        deflated.assert();
+        broken.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_local() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts");
+        fs::write(&path, "docs.rs").unwrap();
+        let path = path.to_str().unwrap();
+        let fetcher = Fetcher::new(&Config::default());
+
+        assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(path));
+        assert_eq!(
+            Some("docs.rs".to_owned()),
+            fetcher.fetch(&format!("file://{path}"))
+        );
+        assert_eq!(Ok(()), fetcher.probe(path));
+        let missing = format!("{}/missing", dir.path().to_str().unwrap());
+        assert_eq!(None, fetcher.fetch(&missing));
+        assert!(fetcher.probe(&missing).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_not_modified() -> Result<(), String> {
+        let modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let changed = mock("GET", "/not-modified")
+            .with_status(200)
+            .with_header("etag", "\"v1\"")
+            .with_header("last-modified", modified)
+            .with_body("docs.rs")
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let url = mockito::server_url() + "/not-modified";
+        let config = Config {
+            http_cache: Some(dir.path().to_str().unwrap().to_owned()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Some("docs.rs".to_owned()),
+            Fetcher::new(&config).fetch(&url)
+        );
+        changed.assert();
+
+        let unchanged = mock("GET", "/not-modified")
+            .match_header("if-none-match", "\"v1\"")
+            .match_header("if-modified-since", modified)
+            .with_status(304)
+            .create();
+        let fetcher = Fetcher::new(&config);
+        assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(&url));
+        assert_eq!(Some(modified.to_owned()), fetcher.last_modified(&url));
+        unchanged.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_user_agent() -> Result<(), String> {
+        let mocked = mock("GET", "/user-agent")
+            .match_header("user-agent", "Mozilla/5.0 (compatible; lists)")
+            .match_header("authorization", "Bearer secret")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+        let url = mockito::server_url() + "/user-agent";
+        let fetcher = Fetcher::new(&Config {
+            user_agent: Some("Mozilla/5.0 (compatible; lists)".to_owned()),
+            sources: HashMap::from([(
+                url.clone(),
+                SourceOptions {
+                    headers: Some(HashMap::from([(
+                        "Authorization".to_owned(),
+                        "Bearer secret".to_owned(),
+                    )])),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        });
+
+        assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(&url));
+        mocked.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_redirect() -> Result<(), String> {
+        let moved = mock("GET", "/moved")
+            .with_status(301)
+            .with_header("location", "/moved-here")
+            .expect(2)
+            .create();
+        let target = mock("GET", "/moved-here")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+        let url = mockito::server_url() + "/moved";
+
+        let fetcher = Fetcher::new(&Config {
+            max_redirects: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(&url));
+
+        let fetcher = Fetcher::new(&Config {
+            max_redirects: Some(0),
+            ..Default::default()
+        });
+        assert_eq!(None, fetcher.fetch(&url));
+        assert_eq!(vec![(url, Failure::Status(301))], fetcher.failures());
+        moved.assert();
+        target.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_stopped() -> Result<(), String> {
+        static STOPS: AtomicUsize = AtomicUsize::new(0);
+        let mock = mock("GET", "/stopped")
+            .with_status(200)
+            .with_body("docs.rs")
+            .expect(1)
+            .create();
+        let url = mockito::server_url() + "/stopped";
+        let fetcher = Fetcher::new(&Config::default()).with_stop(StopSignal::of(&STOPS));
+
+        assert_eq!(Some("docs.rs".to_owned()), fetcher.fetch(&url));
+        STOPS.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(None, fetcher.fetch(&url));
+
+        mock.assert();
+        // A stop requested earlier does not affect a later run.
+        let fetcher = Fetcher::new(&Config::default()).with_stop(StopSignal::of(&STOPS));
+        assert!(!fetcher.stop.is_stopped());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_lines() -> Result<(), String> {
+        let body = (0..1000)
+            .map(|num| format!("d{num}.docs.rs\r\n"))
+            .collect::<String>();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes()).unwrap();
+        let plain = mock("GET", "/lines")
+            .with_status(200)
+            .with_body(&body)
+            .create();
+        let compressed = mock("GET", "/lines.gz")
+            .with_status(200)
+            .with_body(encoder.finish().unwrap())
+            .create();
+        let fetcher = Fetcher::new(&Config::default());
+
+        for url in ["/lines", "/lines.gz"].map(|path| mockito::server_url() + path) {
+            let have = fetcher.fetch_lines(&url, |lines| lines.take(2).collect::<Vec<_>>());
+
+            assert_eq!(
+                Some(vec!["d0.docs.rs".to_owned(), "d1.docs.rs".to_owned()]),
+                have
+            );
+            // Only the lines taken count as fetched.
+            let outcome = fetcher
+                .source_outcomes()
+                .into_iter()
+                .find(|(have, _)| *have == url);
+            assert_eq!(Some((url, Ok(24))), outcome);
+        }
+        plain.assert();
+        compressed.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_lines_failed() -> Result<(), String> {
+        let mock = mock("GET", "/lines-failed")
+            .with_status(200)
+            .with_header("content-encoding", "deflate")
+            .with_body("docs.rs")
+            .create();
+        let url = mockito::server_url() + "/lines-failed";
+        let fetcher = Fetcher::new(&Config::default());
+
+        assert_eq!(None, fetcher.fetch_lines(&url, |lines| lines.count()));
+        assert_eq!(vec![(url, Failure::Decompress)], fetcher.failures());
+        mock.assert();
+        Ok(())
+    }
+
+    /// Self-signed certificate of `localhost` and its key.
+    fn self_signed(serial: u32) -> (X509, PKey<Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(serial).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let san = SubjectAlternativeName::new()
+            .dns("localhost")
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    #[test]
+    fn test_fetch_pinned_cert_mismatch() -> Result<(), String> {
+        let (served, key) = self_signed(1);
+        let (other, _) = self_signed(2);
+        let dir = tempfile::tempdir().unwrap();
+        let pem = |name: &str, cert: &X509| {
+            let path = dir.path().join(name);
+            fs::write(&path, cert.to_pem().unwrap()).unwrap();
+            path.to_str().unwrap().to_owned()
+        };
+        let (served_pem, other_pem) = (pem("served.pem", &served), pem("other.pem", &other));
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor.set_private_key(&key).unwrap();
+        acceptor.set_certificate(&served).unwrap();
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "https://localhost:{}/pinned",
+            listener.local_addr().unwrap().port()
+        );
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A client that rejects the certificate fails the handshake.
+                let Ok(mut stream) = acceptor.accept(stream) else {
+                    continue;
+                };
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\ndocs.rs",
+                );
+            }
+        });
+        let fetcher = |pinned_cert: String| {
+            Fetcher::new(&Config {
+                sources: HashMap::from([(
+                    url.clone(),
+                    SourceOptions {
+                        pinned_cert: Some(pinned_cert),
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            })
+        };
+
+        assert_eq!(Some("docs.rs".to_owned()), fetcher(served_pem).fetch(&url));
+        let fetcher = fetcher(other_pem);
+        assert_eq!(None, fetcher.fetch(&url));
+        assert_eq!(vec![(url, Failure::Request)], fetcher.failures());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_source() -> Result<(), String> {
+        for entry in [
+            "https://docs.rs/hosts",
+            "http://docs.rs",
+            "file:///etc/hosts",
+            "/etc/hosts",
+            "inline://own",
+        ] {
+            assert!(super::is_source(entry), "{entry}");
+        }
+        for entry in [
+            "docs.rs",
+            "docs.rs:443",
+            "name:1",
+            "c:config.yaml",
+            "ftp://docs.rs",
+        ] {
+            assert!(!super::is_source(entry), "{entry}");
+        }
+        Ok(())
+    }
+}
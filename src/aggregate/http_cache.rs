@@ -0,0 +1,51 @@
+use crate::store::{fingerprint, write_atomic};
+use std::fs;
+use std::io;
+
+/// Body of a source together with the validators it was served with.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Cached {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: String,
+}
+
+/// Extension of the cache files, so they don't collide with the ones of the parse cache.
+const EXTENSION: &str = "body";
+
+/// Name of the cache file of the source.
+fn file(url: &str) -> String {
+    format!("{}.{}", fingerprint(url.as_bytes()), EXTENSION)
+}
+
+/// Loads the last body of the source.
+///
+/// A cache file consists of the `ETag` line, the `Last-Modified` line, both empty if not sent,
+/// followed by the body.
+pub fn load(dir: &str, url: &str) -> Option<Cached> {
+    let data = fs::read_to_string(format!("{}/{}", dir, file(url))).ok()?;
+    let mut parts = data.splitn(3, '\n');
+    let header = |value: &str| (!value.is_empty()).then(|| value.to_owned());
+    Some(Cached {
+        etag: header(parts.next()?),
+        last_modified: header(parts.next()?),
+        body: parts.next()?.to_owned(),
+    })
+}
+
+/// Stores the body of the source, replacing the previous one.
+///
+/// The file is replaced atomically, so a concurrent [load] never sees a partially written file.
+///
+/// # Errors
+/// - If the cache directory or file could not be written.
+pub fn save(dir: &str, url: &str, cached: &Cached) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let data = format!(
+        "{}\n{}\n{}",
+        cached.etag.as_deref().unwrap_or_default(),
+        cached.last_modified.as_deref().unwrap_or_default(),
+        cached.body
+    );
+    write_atomic(dir, &file(url), data.as_bytes())
+}
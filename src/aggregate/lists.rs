@@ -1,90 +1,866 @@
-use crate::aggregate::validation;
-use crate::data::{Addlist, AddlistConfig};
+use crate::aggregate::cache;
+use crate::aggregate::fetch::{is_source, Fetcher};
+use crate::aggregate::{cname, validation};
+use crate::config::{Config, OutputFormat, SortOrder, SourceFormat, SplitBySource};
+use crate::data::{Addlist, AddlistConfig, AddlistSources, SourceKind, Whitelist};
+use crate::store::fingerprint;
 use itertools::Itertools;
-use reqwest::blocking::Client;
-use std::collections::HashSet;
+use log::warn;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use regex::Regex;
+use reqwest::Url;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::thread;
 
 pub const DOT: char = '.';
 const WWW: &str = "www.";
-const COMMENT: char = '#';
+pub const COMMENT: char = '#';
+/// Prefix of a whitelist entry that matches all subdomains of the domain following it.
+pub const WILDCARD: &str = "*.";
+/// Prefix of an AdBlock Plus rule blocking a domain.
+const ABP_BLOCK: &str = "||";
+/// Prefix of an AdBlock Plus rule excepting a domain.
+const ABP_EXCEPTION: &str = "@@||";
+const ABP_SEPARATOR: char = '^';
+const ABP_OPTIONS: char = '$';
+/// Markers of AdBlock Plus cosmetic rules, which hide elements instead of blocking domains.
+const ABP_COSMETIC: [&str; 4] = ["##", "#@#", "#?#", "#$#"];
+/// Prefixes of AdBlock Plus comments and headers like `[Adblock Plus 2.0]`.
+const ABP_COMMENTS: [char; 2] = ['!', '['];
+
+/// Domains of a parsed source.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Parsed {
+    /// Valid unique domains.
+    pub domains: HashSet<String>,
+    /// Valid unique domains of exception rules, which must not be blocked.
+    pub exceptions: HashSet<String>,
+    /// Valid domains before they are deduplicated.
+    pub entries: usize,
+    /// Whether parsing stopped early at the maximum number of domains.
+    pub capped: bool,
+}
 
 /// Creates Addlist
-pub fn addlist(config: &AddlistConfig, global_whitelist: Arc<HashSet<String>>) -> Option<Addlist> {
-    let client = Client::new();
+///
+/// Sources that could not be fetched are skipped, reported and kept in [Addlist].failed_sources.
+/// Sources skipped in [Config].offline mode did not fail and are not kept.
+/// The domains of the [AddlistSources].blacklist are always added, even if they are whitelisted.
+///
+/// With [Config].last_modified each domain is dated with the `Last-Modified` of the most trusted source listing it,
+/// or the first one of equally trusted sources.
+pub fn addlist(
+    config: &AddlistConfig,
+    global_whitelist: Arc<Whitelist>,
+    fetcher: &Fetcher,
+) -> Option<Addlist> {
     let sources = config.config.addlist.get(&config.name)?;
-    let local_whitelist = match whitelist(&sources.whitelist) {
-        Some(local_whitelist) => local_whitelist,
-        None => HashSet::new(),
+    match sources.kind {
+        SourceKind::Domains => {}
+        SourceKind::Regex => return Some(raw_addlist(config, sources, fetcher, parse_regexes)),
+        SourceKind::Ip => return Some(raw_addlist(config, sources, fetcher, parse_ips)),
+    }
+    let whitelisted = whitelisted(config, sources, &global_whitelist, fetcher);
+
+    // Number of sources that list a domain, each weighted by one plus its trust.
+    let consensus = config.config.sort == SortOrder::ByConsensus;
+    let mut counts = HashMap::new();
+    // Most trusted source that lists a domain, only tracked for the last modified dates.
+    // Of equally trusted sources the first one is kept.
+    let provenance = config.config.last_modified;
+    let trust = |url: &str| {
+        config
+            .config
+            .sources
+            .get(url)
+            .map_or(0, |options| options.trust)
+    };
+    let mut attributed: HashMap<String, &String> = HashMap::new();
+    let mut failed_sources = Vec::new();
+    let mut exceptions = HashSet::new();
+    let concurrency = config.config.fetch_concurrency.map_or(1, NonZeroUsize::get);
+    let urls = order(&config.config, &sources.addlist);
+    // Domains that still fit into the addlist, see [collect_capped].
+    let remaining = Cell::new(config.config.max_domains.map(NonZeroUsize::get));
+    let found = urls
+        .chunks(concurrency)
+        .take_while(|_| remaining.get() != Some(0))
+        .flat_map(|batch| fetch_batch(config, batch, fetcher, remaining.get()))
+        .filter_map(|(url, fetched)| match fetched {
+            Some((domains, source_exceptions)) => {
+                exceptions.extend(source_exceptions);
+                Some((url, domains))
+            }
+            None => {
+                if !fetcher.skips_offline(url) {
+                    failed_sources.push(url.clone());
+                }
+                None
+            }
+        })
+        .flat_map(|(url, domains)| domains.into_iter().map(move |domain| (url, domain)))
+        .map(|(url, domain)| {
+            if consensus {
+                *counts.entry(domain.clone()).or_default() += 1 + trust(url) as usize;
+            }
+            if provenance {
+                attributed
+                    .entry(domain.clone())
+                    .and_modify(|source| {
+                        if trust(url) > trust(source) {
+                            *source = url;
+                        }
+                    })
+                    .or_insert(url);
+            }
+            domain
+        });
+    let domains = collect_capped(config, found, &remaining);
+    if !failed_sources.is_empty() {
+        warn!(
+            "Addlist {}: {} of {} sources failed: {}",
+            config.name,
+            failed_sources.len(),
+            sources.addlist.len(),
+            failed_sources.join(", ")
+        );
+    }
+    let domains = if config.expands_cnames() {
+        cname::expand(domains, fetcher.cname_limiter())
+    } else {
+        domains
     };
-    let local_reduced_whitelist: HashSet<_> =
-        local_whitelist.difference(&global_whitelist).collect();
 
-    let data = sources
-        .addlist
+    // Exception rules of any source apply to the whole addlist.
+    // Blacklisted domains are never whitelisted, they are parsed like a local whitelist.
+    let blacklist = local_whitelist(&sources.blacklist, fetcher).unwrap_or_default();
+    let whitelisted = |domain: &str| {
+        !blacklist.contains(domain) && (exceptions.contains(domain) || whitelisted(domain))
+    };
+    let (excepted, mut data): (HashSet<String>, HashSet<String>) =
+        domains.into_iter().partition(|domain| whitelisted(domain));
+    data.extend(blacklist.iter().cloned());
+    let last_modified = data
         .iter()
-        .filter_map(|url| fetch(url, &client))
-        .flat_map(parse)
-        .filter(|domain| !global_whitelist.contains(domain))
-        .filter(|domain| !local_reduced_whitelist.contains(domain))
+        .filter_map(|domain| {
+            let modified = fetcher.last_modified(attributed.get(domain)?)?;
+            Some((domain.clone(), modified))
+        })
         .collect();
 
+    let mut list = mutate_ranked(config, data, consensus.then_some(&counts), &whitelisted);
+    if config.is_ublock() && config.config.ublock_exceptions {
+        list.extend(
+            excepted
+                .iter()
+                .sorted()
+                .map(|domain| ublock_filter(domain, true)),
+        );
+    }
+
+    let removed = if config.config.audit {
+        excepted.into_iter().sorted().collect()
+    } else {
+        Vec::new()
+    };
+
     Some(Addlist {
-        list: mutate(config, data),
+        list,
         name: config.name.clone(),
+        last_modified,
+        failed_sources,
+        removed,
     })
 }
 
-/// Creates Whitelist
-pub fn whitelist(sources: &Option<HashSet<String>>) -> Option<HashSet<String>> {
-    if let Some(sources) = sources {
-        let client = Client::new();
-        Some(sources
+/// Domains and exceptions of a source.
+type SourceDomains = (HashSet<String>, HashSet<String>);
+
+/// Fetches and parses the sources of a batch at the same time, see [Config].fetch_concurrency.
+///
+/// Each source is parsed up to `max` domains, the budget left of [Config].max_domains.
+/// The results are in the order of the sources.
+fn fetch_batch<'a>(
+    config: &AddlistConfig,
+    batch: &[&'a String],
+    fetcher: &Fetcher,
+    max: Option<usize>,
+) -> Vec<(&'a String, Option<SourceDomains>)> {
+    if let [url] = batch {
+        return vec![(*url, fetch_domains(config, url, fetcher, max))];
+    }
+    thread::scope(|scope| {
+        let handles = batch
             .iter()
-            .filter_map(|url| fetch(url, &client))
-            .flat_map(parse)
-            .collect())
-    }else {
-        None
+            .map(|url| scope.spawn(move || fetch_domains(config, url, fetcher, max)))
+            .collect_vec();
+        batch
+            .iter()
+            .zip(handles)
+            // A panicking fetch counts as a failed source.
+            .map(|(url, handle)| (*url, handle.join().unwrap_or_default()))
+            .collect()
+    })
+}
+
+/// Collects the domains of an addlist up to [Config].max_domains.
+///
+/// Once the cap is reached no further domains are taken,
+/// so the remaining sources are not even fetched.
+/// The number of domains that still fit is kept up to date in `remaining`.
+fn collect_capped(
+    config: &AddlistConfig,
+    domains: impl Iterator<Item = String>,
+    remaining: &Cell<Option<usize>>,
+) -> HashSet<String> {
+    let Some(max) = config.config.max_domains else {
+        return domains.collect();
+    };
+    let mut collected = HashSet::new();
+    for domain in domains {
+        if collected.len() >= max.get() && !collected.contains(&domain) {
+            warn!(
+                "Addlist {} reached the maximum of {max} domains, further domains are dropped.",
+                config.name
+            );
+            break;
+        }
+        collected.insert(domain);
+        remaining.set(Some(max.get() - collected.len()));
     }
+    collected
 }
 
-/// Fetches raw domain data
-fn fetch(url: &String, client: &Client) -> Option<String> {
-    let response = client.get(url).send().ok()?;
-    if response.status() == 200 {
-        return response.text().ok();
+/// Creates an Addlist of entries that are no domains, like [SourceKind::Regex] or [SourceKind::Ip].
+///
+/// The entries are neither whitelisted nor mutated, so they are written as they are parsed.
+fn raw_addlist(
+    config: &AddlistConfig,
+    sources: &AddlistSources,
+    fetcher: &Fetcher,
+    parse: fn(&str) -> HashSet<String>,
+) -> Addlist {
+    let mut failed_sources = Vec::new();
+    let mut entries = HashSet::new();
+    for url in order(&config.config, &sources.addlist) {
+        match fetcher.fetch(url) {
+            Some(raw_data) => entries.extend(parse(&raw_data)),
+            None if fetcher.skips_offline(url) => {}
+            None => failed_sources.push(url.clone()),
+        }
+    }
+    Addlist {
+        name: config.name.clone(),
+        list: entries.into_iter().sorted().collect(),
+        failed_sources,
+        ..Default::default()
     }
-    None
 }
 
-/// Parses a raw data to a HashSet of valid domains.
+/// Parses a Pi-hole regex list with one regex per line.
 ///
-/// Raw data is parsed to valid unique domains.
-fn parse(raw_data: String) -> HashSet<String> {
+/// Enclosing `/` are removed and regexes that do not compile are dropped.
+pub fn parse_regexes(raw_data: &str) -> HashSet<String> {
     raw_data
-        .to_lowercase()
         .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(COMMENT))
         .map(|line| {
-            line.find(COMMENT)
-                .map(|index| line[..index].as_ref())
+            line.strip_prefix('/')
+                .and_then(|line| line.strip_suffix('/'))
                 .unwrap_or(line)
         })
-        .flat_map(|line| line.split_whitespace())
-        .filter_map(validation::validate)
+        .filter(|regex| match Regex::new(regex) {
+            Ok(_) => true,
+            Err(err) => {
+                warn!("Dropped invalid regex {regex}: {err}");
+                false
+            }
+        })
+        .map(str::to_owned)
         .collect()
 }
 
+/// Parses a list of IP addresses with one address per line.
+///
+/// Only the first word of a line is read, lines that are no IPv4 or IPv6 address are dropped.
+/// The addresses are normalized, like `::1` for `0:0:0:0:0:0:0:1`.
+pub fn parse_ips(raw_data: &str) -> HashSet<String> {
+    raw_data
+        .lines()
+        .filter_map(|line| line.split(COMMENT).next()?.split_whitespace().next())
+        .filter_map(|ip| IpAddr::from_str(ip).ok())
+        .map(|ip| ip.to_string())
+        .collect()
+}
+
+/// Name of the Addlist of a source when the addlist is split by source, `{addlist}-{host}`.
+///
+/// Sources without a host are named by the url with every non-alphanumeric character replaced by `_`.
+pub fn source_addlist_name(addlist: &str, url: &str) -> String {
+    let host = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_owned))
+        .unwrap_or_else(|| url.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+    format!("{}-{}", addlist, host)
+}
+
+/// Creates an Addlist per source host of the addlist, named by [source_addlist_name].
+///
+/// Sources of the same host share an Addlist.
+/// The whitelists are only applied with [SplitBySource::PostWhitelist].
+/// The Addlists hold at most [Config].max_domains domains together, the remaining sources are not fetched.
+pub fn addlists_by_source(
+    config: &AddlistConfig,
+    split: SplitBySource,
+    global_whitelist: Arc<Whitelist>,
+    fetcher: &Fetcher,
+) -> Option<Vec<Addlist>> {
+    let sources = config.config.addlist.get(&config.name)?;
+    match sources.kind {
+        SourceKind::Domains => {}
+        SourceKind::Regex => {
+            return Some(vec![raw_addlist(config, sources, fetcher, parse_regexes)])
+        }
+        SourceKind::Ip => return Some(vec![raw_addlist(config, sources, fetcher, parse_ips)]),
+    }
+    let whitelisted = whitelisted(config, sources, &global_whitelist, fetcher);
+
+    let mut hosts: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut exceptions = HashSet::new();
+    let mut remaining = config.config.max_domains.map(NonZeroUsize::get);
+    for url in order(&config.config, &sources.addlist) {
+        if remaining == Some(0) {
+            warn!(
+                "Addlist {} reached the maximum of {} domains, further domains are dropped.",
+                config.name,
+                config.config.max_domains.map_or(0, NonZeroUsize::get)
+            );
+            break;
+        }
+        if let Some((domains, source_exceptions)) = fetch_domains(config, url, fetcher, remaining) {
+            exceptions.extend(source_exceptions);
+            let list = hosts
+                .entry(source_addlist_name(&config.name, url))
+                .or_default();
+            let before = list.len();
+            list.extend(domains);
+            remaining = remaining.map(|remaining| remaining - (list.len() - before));
+        }
+    }
+
+    let whitelisted = |domain: &str| exceptions.contains(domain) || whitelisted(domain);
+    let addlists = hosts
+        .into_iter()
+        .sorted_by(|a, b| a.0.cmp(&b.0))
+        .map(|(name, domains)| {
+            let domains = if config.expands_cnames() {
+                cname::expand(domains, fetcher.cname_limiter())
+            } else {
+                domains
+            };
+            let list = match split {
+                SplitBySource::PreWhitelist => mutate(config, domains),
+                SplitBySource::PostWhitelist => {
+                    let data = domains
+                        .into_iter()
+                        .filter(|domain| !whitelisted(domain))
+                        .collect();
+                    mutate_ranked(config, data, None, &whitelisted)
+                }
+            };
+            Addlist {
+                list,
+                name,
+                ..Default::default()
+            }
+        })
+        .collect();
+    Some(addlists)
+}
+
+/// Creates the check whether a domain is whitelisted for the addlist.
+///
+/// The global whitelist is skipped if the addlist does not apply it,
+/// while [Config].always_allow applies to every addlist.
+fn whitelisted<'a>(
+    config: &AddlistConfig,
+    sources: &AddlistSources,
+    global_whitelist: &'a Whitelist,
+    fetcher: &Fetcher,
+) -> impl Fn(&str) -> bool + 'a {
+    let global_whitelist = sources
+        .apply_global_whitelist
+        .unwrap_or(true)
+        .then_some(global_whitelist);
+    let mut local_whitelist = local_whitelist(&sources.whitelist, fetcher).unwrap_or_default();
+    local_whitelist.extend(
+        config
+            .config
+            .always_allow
+            .iter()
+            .filter_map(whitelist_entry),
+    );
+    let local_reduced_whitelist: HashSet<_> = match global_whitelist {
+        Some(global_whitelist) => local_whitelist
+            .difference(&global_whitelist.domains)
+            .cloned()
+            .collect(),
+        None => local_whitelist,
+    };
+    let local_reduced_whitelist = Whitelist::from(local_reduced_whitelist);
+    // Skeletons of the whitelisted domains with the whitelist and the entry they belong to.
+    let confusable_whitelist: HashMap<_, _> = if config.config.confusable_whitelist {
+        global_whitelist
+            .iter()
+            .flat_map(|global_whitelist| {
+                global_whitelist
+                    .domains
+                    .iter()
+                    .map(|domain| (true, domain.clone()))
+            })
+            .chain(
+                local_reduced_whitelist
+                    .domains
+                    .iter()
+                    .map(|domain| (false, domain.clone())),
+            )
+            .map(|(global, domain)| (validation::skeleton(&domain), (global, domain)))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    move |domain| {
+        if global_whitelist.is_some_and(|global_whitelist| global_whitelist.contains(domain))
+            || local_reduced_whitelist.contains(domain)
+        {
+            return true;
+        }
+        if confusable_whitelist.is_empty() {
+            return false;
+        }
+        let Some((global, entry)) = confusable_whitelist.get(&validation::skeleton(domain)) else {
+            return false;
+        };
+        // A confusable match counts as hit of the whitelisted domain.
+        match global_whitelist.filter(|_| *global) {
+            Some(global_whitelist) => global_whitelist.hit(entry),
+            None => local_reduced_whitelist.hit(entry),
+        }
+        true
+    }
+}
+
+/// Fetches and parses a source of the addlist.
+///
+/// JSON and CSV sources are converted into one entry per line first, see [extract].
+/// Reserved names are dropped if [Config].drop_reserved is set.
+/// At most `max` domains are parsed, see [parse_rules_capped].
+/// A capped line based source is parsed while it is received, so it is not received beyond the cap,
+/// unless its domains are cached in [Config].validation_cache.
+/// Returns the domains and the exceptions of the source.
+fn fetch_domains(
+    config: &AddlistConfig,
+    url: &str,
+    fetcher: &Fetcher,
+    max: Option<usize>,
+) -> Option<SourceDomains> {
+    let options = config.config.sources.get(url);
+    let normalize_dots = config.config.normalize_dots;
+    let lines = options.is_none_or(|options| options.format == SourceFormat::Text);
+    let Parsed {
+        domains,
+        exceptions,
+        entries,
+        capped,
+    } = match &config.config.validation_cache {
+        None if max.is_some() && lines => {
+            fetcher.fetch_lines(url, |lines| parse_lines(lines, normalize_dots, max))?
+        }
+        validation_cache => {
+            let raw_data = fetcher.fetch(url)?;
+            let raw_data = match options {
+                Some(options) => extract(raw_data, options.format, options.csv_column)
+                    .map_err(|err| warn!("Failed to read {url}: {err}"))
+                    .ok()?,
+                None => raw_data,
+            };
+            match validation_cache {
+                Some(dir) => parse_cached(dir, url, raw_data, normalize_dots, max),
+                None => parse_rules_capped(raw_data, normalize_dots, max),
+            }
+        }
+    };
+    if capped {
+        warn!(
+            "Source {url} of addlist {} has more domains than still fit, further domains are dropped.",
+            config.name
+        );
+    }
+    let domains = if config.config.drop_reserved {
+        domains
+            .into_iter()
+            .filter(|domain| !validation::is_reserved(domain))
+            .collect()
+    } else {
+        domains
+    };
+    if config.config.report_duplicates && entries > domains.len() {
+        warn!(
+            "Source {url} contains {} duplicate domains.",
+            entries - domains.len()
+        );
+    }
+    Some((domains, exceptions))
+}
+
+/// Orders the sources in which they are fetched.
+///
+/// Sources are sorted, unless [Config].shuffle_sources is set.
+/// Then they are shuffled with [Config].seed if set.
+fn order<'a>(config: &Config, sources: &'a HashSet<String>) -> Vec<&'a String> {
+    let mut sources = sources.iter().sorted().collect_vec();
+    if config.shuffle_sources {
+        let mut rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        sources.shuffle(&mut rng);
+    }
+    sources
+}
+
+/// Combines multiple addlists into a single one.
+///
+/// The addlists are processed sorted by name and each entry is kept in the first addlist that contains it,
+/// the same applies to the last modified dates of the domains.
+/// Removed domains are only kept if no addlist contains them.
+/// With separators each addlist is introduced by a `# ---- {name} ----` line, otherwise all entries are sorted.
+pub fn combine(name: &str, mut addlists: Vec<Addlist>, separators: bool) -> Addlist {
+    addlists.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut seen = HashSet::new();
+    let mut list = Vec::new();
+    let mut last_modified = BTreeMap::new();
+    let mut removed = BTreeSet::new();
+    for addlist in addlists {
+        removed.extend(addlist.removed);
+        for (domain, modified) in addlist.last_modified {
+            last_modified.entry(domain).or_insert(modified);
+        }
+        let entries = addlist
+            .list
+            .into_iter()
+            .filter(|entry| seen.insert(entry.clone()))
+            .collect_vec();
+        if separators && !entries.is_empty() {
+            list.push(separator(&addlist.name));
+        }
+        list.extend(entries);
+    }
+    if !separators {
+        list.sort();
+    }
+    removed.retain(|domain| !seen.contains(domain));
+
+    Addlist {
+        name: name.to_owned(),
+        list,
+        last_modified,
+        removed: removed.into_iter().collect(),
+        ..Default::default()
+    }
+}
+
+/// Removes every entry from an addlist that is already part of an addlist with a lower name.
+///
+/// The addlists are returned sorted by name.
+pub fn dedupe(mut addlists: Vec<Addlist>) -> Vec<Addlist> {
+    addlists.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut seen = HashSet::new();
+    let mut seen_domains = HashSet::new();
+    for addlist in &mut addlists {
+        addlist.list.retain(|entry| seen.insert(entry.clone()));
+        addlist
+            .last_modified
+            .retain(|domain, _| seen_domains.insert(domain.clone()));
+    }
+    addlists
+}
+
+/// Comment line introducing the entries of an addlist within a combined addlist.
+pub fn separator(name: &str) -> String {
+    format!("{} ---- {} ----", COMMENT, name)
+}
+
+/// Creates Whitelist
+pub fn whitelist(sources: &Option<HashSet<String>>, fetcher: &Fetcher) -> Option<HashSet<String>> {
+    sources.as_ref().map(|sources| {
+        sources
+            .iter()
+            .filter_map(|url| fetcher.fetch(url))
+            .flat_map(|raw_data| {
+                let suffixes: Vec<_> = raw_data
+                    .lines()
+                    .filter_map(|line| line.split_whitespace().next())
+                    .filter(|entry| entry.starts_with(WILDCARD) || entry.starts_with(DOT))
+                    .filter_map(whitelist_entry)
+                    .collect();
+                // Exception rules of a whitelist source are whitelisted as well.
+                let parsed = parse_rules(raw_data, false);
+                parsed
+                    .domains
+                    .into_iter()
+                    .chain(parsed.exceptions)
+                    .chain(suffixes)
+            })
+            .collect()
+    })
+}
+
+/// Validates an entry of a whitelist.
+///
+/// Entries like `*.example.com` or `.example.com` whitelist all subdomains of `example.com`
+/// and are returned as `*.example.com`, see [Whitelist].
+fn whitelist_entry<S: AsRef<str>>(entry: S) -> Option<String> {
+    let entry = entry.as_ref().to_lowercase();
+    match entry
+        .strip_prefix(WILDCARD)
+        .or_else(|| entry.strip_prefix(DOT))
+    {
+        Some(suffix) => {
+            // Validated as a subdomain, so a single label like `local` is a valid suffix as well.
+            let subdomain = validation::validate(&format!("w.{suffix}"))?;
+            Some(format!("{WILDCARD}{}", &subdomain[2..]))
+        }
+        None => validation::validate(&entry),
+    }
+}
+
+/// Creates the Whitelist of an addlist.
+///
+/// Sources, like `https://` URLs or absolute paths, are fetched, all other entries are taken as inline domains,
+/// even if they parse as URL like `docs.rs:443`, see [is_source].
+fn local_whitelist(
+    sources: &Option<HashSet<String>>,
+    fetcher: &Fetcher,
+) -> Option<HashSet<String>> {
+    sources.as_ref().map(|sources| {
+        let (urls, domains): (HashSet<_>, HashSet<_>) = sources
+            .iter()
+            .cloned()
+            .partition(|source| is_source(source));
+        let inline = domains.iter().filter_map(whitelist_entry);
+        whitelist(&Some(urls), fetcher)
+            .unwrap_or_default()
+            .into_iter()
+            .chain(inline)
+            .collect()
+    })
+}
+
+/// Converts the raw data of a source in the given format into one entry per line.
+///
+/// A JSON source must be an array of strings, a CSV source has the entries in `column`.
+/// Values in CSV may be quoted, but must not contain commas.
+///
+/// # Errors
+/// - If a JSON source is not an array of strings.
+pub fn extract(raw_data: String, format: SourceFormat, column: usize) -> Result<String, String> {
+    match format {
+        SourceFormat::Text => Ok(raw_data),
+        SourceFormat::Json => serde_json::from_str::<Vec<String>>(&raw_data)
+            .map(|entries| entries.join("\n"))
+            .map_err(|err| format!("not a JSON array of domains: {err}")),
+        SourceFormat::Csv => Ok(raw_data
+            .lines()
+            .filter_map(|line| line.split(',').nth(column))
+            .map(|value| value.trim().trim_matches('"'))
+            .join("\n")),
+    }
+}
+
+/// Parses a raw data to a HashSet of valid domains.
+///
+/// Raw data is parsed to valid unique domains.
+pub fn parse(raw_data: String) -> HashSet<String> {
+    parse_with(raw_data, false)
+}
+
+/// Parses a raw data to a HashSet of valid domains.
+///
+/// Dots of each domain are normalized before validation if `normalize_dots` is set.
+pub fn parse_with(raw_data: String, normalize_dots: bool) -> HashSet<String> {
+    parse_counted(raw_data, normalize_dots).0
+}
+
+/// Parses a raw data like [parse_with] and counts the valid entries before they are deduplicated.
+pub fn parse_counted(raw_data: String, normalize_dots: bool) -> (HashSet<String>, usize) {
+    let parsed = parse_rules(raw_data, normalize_dots);
+    (parsed.domains, parsed.entries)
+}
+
+/// Parses a raw data of hosts entries and AdBlock Plus rules.
+///
+/// `||domain^` rules are taken as domains and `@@||domain^` rules as exceptions.
+/// Comments starting with `!` and headers like `[Adblock Plus 2.0]` are skipped.
+/// Rules with a path, a wildcard or a cosmetic filter like `example.com##.ad` are dropped as a whole.
+pub fn parse_rules(raw_data: String, normalize_dots: bool) -> Parsed {
+    parse_rules_capped(raw_data, normalize_dots, None)
+}
+
+/// Parses a raw data like [parse_rules], stopping at the first domain beyond `max` unique domains.
+///
+/// So the domains of a huge source are never held in memory as a whole, see [Config].max_domains.
+/// Exceptions after that domain are not parsed either.
+pub fn parse_rules_capped(raw_data: String, normalize_dots: bool, max: Option<usize>) -> Parsed {
+    parse_lines(raw_data.lines(), normalize_dots, max)
+}
+
+/// Parses lines like [parse_rules_capped], taking no further line once it stopped.
+///
+/// So a source whose lines are read while it is received is not received beyond the cap,
+/// see [Fetcher::fetch_lines].
+pub fn parse_lines(
+    lines: impl IntoIterator<Item = impl AsRef<str>>,
+    normalize_dots: bool,
+    max: Option<usize>,
+) -> Parsed {
+    let validate = |domain: &str| {
+        if normalize_dots {
+            validation::validate(&validation::normalize_dots(domain))
+        } else {
+            validation::validate(domain)
+        }
+    };
+    let mut parsed = Parsed::default();
+    for line in lines {
+        // Lowercased per line, so the data is not copied as a whole.
+        let line = line.as_ref().trim().to_lowercase();
+        let line = line.as_str();
+        if line.starts_with(ABP_COMMENTS) {
+            continue;
+        }
+        let first = line.split_whitespace().next().unwrap_or_default();
+        if ABP_COSMETIC.iter().any(|marker| first.contains(marker)) {
+            continue;
+        }
+        if let Some(rule) = line.strip_prefix(ABP_EXCEPTION) {
+            parsed
+                .exceptions
+                .extend(abp_domain(rule).and_then(validate));
+            continue;
+        }
+        let domains: Vec<String> = match line.strip_prefix(ABP_BLOCK) {
+            Some(rule) => abp_domain(rule).and_then(validate).into_iter().collect(),
+            None => line
+                .find(COMMENT)
+                .map(|index| line[..index].as_ref())
+                .unwrap_or(line)
+                .split_whitespace()
+                .filter_map(validate)
+                .collect(),
+        };
+        for domain in domains {
+            if max
+                .is_some_and(|max| parsed.domains.len() >= max && !parsed.domains.contains(&domain))
+            {
+                parsed.capped = true;
+                return parsed;
+            }
+            parsed.entries += 1;
+            parsed.domains.insert(domain);
+        }
+    }
+    parsed
+}
+
+/// Domain of an AdBlock Plus rule without its prefix, like `example.com^$important`.
+///
+/// Rules with anything but options after the separator, a path or a wildcard match more or less than a domain.
+fn abp_domain(rule: &str) -> Option<&str> {
+    let (domain, rest) = rule.split_once(ABP_SEPARATOR)?;
+    let options = rest.is_empty() || rest.starts_with(ABP_OPTIONS);
+    (options && !domain.contains(['/', '*'])).then_some(domain)
+}
+
+/// Parses a raw data like [parse_rules_capped], unless the source had the same raw data in the last run.
+///
+/// Then the domains are taken from the cache in `dir` instead.
+/// Failing to write the cache is reported but does not affect the result.
+/// Capped domains are not cached, as they are incomplete.
+pub fn parse_cached(
+    dir: &str,
+    url: &str,
+    raw_data: String,
+    normalize_dots: bool,
+    max: Option<usize>,
+) -> Parsed {
+    // The dots setting changes the result, so it is part of the hash.
+    let hash = format!(
+        "{}-{}-{}",
+        cache::VERSION,
+        fingerprint(raw_data.as_bytes()),
+        normalize_dots
+    );
+    if let Some(mut parsed) = cache::load(dir, url, &hash) {
+        if let Some(max) = max.filter(|max| parsed.domains.len() > *max) {
+            parsed.domains = parsed.domains.into_iter().take(max).collect();
+            parsed.capped = true;
+        }
+        return parsed;
+    }
+    let parsed = parse_rules_capped(raw_data, normalize_dots, max);
+    if parsed.capped {
+        return parsed;
+    }
+    if let Err(err) = cache::save(dir, url, &hash, &parsed) {
+        warn!("Failed to cache the domains of {url}: {err}");
+    }
+    parsed
+}
+
 /// Muatates domains based on config.
 ///
 /// Adds prefix and suffix as in the configuration defined.
 /// Converts the Set of domains to a sorted vector.
-/// Add/Remove the subdomain `www.` to have both in the addlist.
-fn mutate(config: &AddlistConfig, domains: HashSet<String>) -> Vec<String> {
+/// Add/Remove the subdomain `www.` to have both in the addlist, unless [Config].www_duplication is `false`.
+/// Only registrable domains, like `example.co.uk` as of the Public Suffix List, get or lose `www.`.
+/// Internationalized domains are followed by their unicode form if [Config].emit_both_idn_forms is set,
+/// unless [Config].output_ascii_only is set.
+pub fn mutate(config: &AddlistConfig, domains: HashSet<String>) -> Vec<String> {
+    mutate_ranked(config, domains, None, &|_| false)
+}
+
+/// Mutates domains like [mutate].
+///
+/// With counts the domains are sorted by the number of sources listing them, most listed first.
+/// The subdomain `www.` is neither removed nor added if that results in a whitelisted domain.
+/// The sorted domains are truncated to the limit of the addlist.
+pub fn mutate_ranked(
+    config: &AddlistConfig,
+    domains: HashSet<String>,
+    counts: Option<&HashMap<String, usize>>,
+    whitelisted: &dyn Fn(&str) -> bool,
+) -> Vec<String> {
+    // Formats whose entries match subdomains don't need the `www.` variants.
+    let www = !config.matches_subdomains() && config.config.www_duplication.unwrap_or(true);
     let no_prefix = domains
         .into_iter()
         .map(|domain| {
-            if domain.split(DOT).count() == 3 && domain.starts_with(WWW) {
+            if www
+                && domain.starts_with(WWW)
+                && validation::is_registrable(&domain[WWW.len()..])
+                && !whitelisted(&domain[WWW.len()..])
+            {
                 domain
                     .strip_prefix(WWW)
                     .unwrap_or(domain.as_str())
@@ -99,28 +875,84 @@ fn mutate(config: &AddlistConfig, domains: HashSet<String>) -> Vec<String> {
 
     let prefix = no_prefix
         .iter()
-        .filter(|domain| domain.split(DOT).count() == 2 && !domain.starts_with(WWW))
+        .filter(|domain| www && !domain.starts_with(WWW) && validation::is_registrable(domain))
         .map(|domain| format!("{}{}", WWW, domain))
+        .filter(|domain| !whitelisted(domain))
         .unique()
         .sorted()
         .collect_vec(); // no copy or allocation cost.
 
-    let combined = [no_prefix, prefix].into_iter().flatten();
+    let mut combined = no_prefix;
+    combined.extend(prefix);
+    if let Some(counts) = counts {
+        // The sort is stable, so domains with the same count stay sorted.
+        combined.sort_by_cached_key(|domain| Reverse(rank(counts, domain)));
+    }
+
+    let emit_both = config.config.emit_both_idn_forms && !config.config.output_ascii_only;
+    let combined = combined.into_iter().flat_map(|domain| {
+        let decoded = validation::decode(&domain);
+        let decoded = (emit_both && decoded != domain).then_some(decoded);
+        [Some(domain), decoded].into_iter().flatten()
+    });
 
-    match (&config.config.prefix, &config.config.suffix) {
-        (None, None) => combined.collect(),
-        _ => combined
+    let combined = combined.take(config.limit().map_or(usize::MAX, NonZeroUsize::get));
+
+    if config.is_ublock() {
+        combined
+            .map(|domain| ublock_filter(&domain, false))
+            .collect()
+    } else if config.config.output_format == OutputFormat::Dnsmasq {
+        combined
+            .map(|domain| format!("address=/{domain}/{DNSMASQ_ADDRESS}"))
+            .collect()
+    } else if config.is_decorated() {
+        combined
             .map(|domain| format!("{}{}{}", config.prefix(), domain, config.suffix()))
-            .collect(),
+            .collect()
+    } else {
+        combined.collect()
+    }
+}
+
+/// Address blocked domains resolve to in dnsmasq configs.
+const DNSMASQ_ADDRESS: &str = "0.0.0.0";
+
+/// uBlock Origin static filter blocking the domain and its subdomains, or excepting them.
+fn ublock_filter(domain: &str, exception: bool) -> String {
+    if exception {
+        format!("@@||{domain}^")
+    } else {
+        format!("||{domain}^")
     }
 }
 
+/// Weighted number of sources listing the domain with or without the subdomain `www.`.
+fn rank(counts: &HashMap<String, usize>, domain: &str) -> usize {
+    let domain = domain.strip_prefix(WWW).unwrap_or(domain);
+    let count = counts.get(domain).copied().unwrap_or_default();
+    let www_count = counts
+        .get(&format!("{}{}", WWW, domain))
+        .copied()
+        .unwrap_or_default();
+    count.max(www_count)
+}
+
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
-    use crate::config::Config;
-    use crate::data::{Addlist, AddlistConfig, AddlistSources};
+    use crate::aggregate::fetch::Fetcher;
+    use crate::aggregate::http_cache::{self, Cached};
+    use crate::config::{
+        Config, OutputFormat, SortOrder, SourceFormat, SourceOptions, SplitBySource,
+    };
+    use crate::data::{Addlist, AddlistConfig, AddlistSources, SourceKind, Whitelist};
+    use itertools::Itertools;
     use mockito::mock;
+    use reqwest::Url;
     use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::num::NonZeroUsize;
     use std::sync::Arc;
 
     #[test]
@@ -145,6 +977,7 @@ mod tests {
             AddlistSources {
                 addlist: HashSet::from_iter(vec![url.clone() + "/addlist"]),
                 whitelist: None,
+                ..Default::default()
             },
         );
         config.addlist = addlist;
@@ -154,12 +987,15 @@ mod tests {
             config: Arc::new(config),
         };
 
-        let whitelist = Arc::new(HashSet::from_iter(vec!["www.rust-lang.org".to_owned()]));
+        let whitelist = Arc::new(Whitelist::from(HashSet::from_iter(vec![
+            "www.rust-lang.org".to_owned(),
+        ])));
 
-        let have = super::addlist(&config, whitelist);
+        let have = super::addlist(&config, whitelist, &Fetcher::new(&config.config));
         let want = Some(Addlist {
             name: "Addlist".to_owned(),
             list: vec!["docs.rs".to_owned(), "www.docs.rs".to_owned()],
+            ..Default::default()
         });
 
         mock.assert();
@@ -193,6 +1029,7 @@ mod tests {
             AddlistSources {
                 addlist: HashSet::from_iter(vec![url.clone() + "/addlist"]),
                 whitelist: Some(HashSet::from_iter(vec![url.clone() + "/whitelist"])),
+                ..Default::default()
             },
         );
         config.addlist = addlist;
@@ -202,12 +1039,15 @@ mod tests {
             config: Arc::new(config),
         };
 
-        let whitelist = Arc::new(HashSet::from_iter(vec!["www.rust-lang.org".to_owned()]));
+        let whitelist = Arc::new(Whitelist::from(HashSet::from_iter(vec![
+            "www.rust-lang.org".to_owned(),
+        ])));
 
-        let have = super::addlist(&config, whitelist);
+        let have = super::addlist(&config, whitelist, &Fetcher::new(&config.config));
         let want = Some(Addlist {
             name: "Addlist".to_owned(),
             list: vec!["t.org".to_owned(), "www.t.org".to_owned()],
+            ..Default::default()
         });
 
         mock1.assert();
@@ -217,160 +1057,1746 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_valid() -> Result<(), String> {
-        let raw = vec![
-            String::from("aa") + ".ccccc".repeat(50).as_str() + ".com",
-            String::from("docs.rs"),
-            String::from("rust-lang.org"),
-            String::from("t.org"),
-            String::from("xn--mller-brombel-rmb4fg.de"),
-        ];
-        let want = HashSet::from_iter(raw.clone());
-        let have = super::parse(raw.join("\n"));
+    fn test_addlist_inline_local_whitelist() -> Result<(), String> {
+        // Set up environment
+        let mock1 = mock("GET", "/inline-addlist")
+            .with_status(200)
+            .with_body("docs.rs\ncrates.io\nt.org")
+            .create();
+        let mock2 = mock("GET", "/inline-whitelist")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+
+        let url = &mockito::server_url();
+
+        let mut config = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+
+        let mut addlist = HashMap::new();
+        addlist.insert(
+            "Addlist".to_owned(),
+            AddlistSources {
+                addlist: HashSet::from_iter(vec![url.clone() + "/inline-addlist"]),
+                whitelist: Some(HashSet::from_iter(vec![
+                    url.clone() + "/inline-whitelist",
+                    "Crates.io".to_owned(),
+                ])),
+                ..Default::default()
+            },
+        );
+        config.addlist = addlist;
+
+        let config = AddlistConfig {
+            name: "Addlist".to_owned(),
+            config: Arc::new(config),
+        };
+
+        let whitelist = Arc::new(Whitelist::from(HashSet::new()));
+
+        let have = super::addlist(&config, whitelist, &Fetcher::new(&config.config));
+        let want = Some(Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["t.org".to_owned(), "www.t.org".to_owned()],
+            ..Default::default()
+        });
+
+        mock1.assert();
+        mock2.assert();
         assert_eq!(want, have);
         Ok(())
     }
 
     #[test]
-    fn test_parse_invaid() -> Result<(), String> {
-        let raw = vec![
-            String::from("-analytics/analytics."),
-            String::from("::1"),
-            String::from(".php?action_name="),
-            String::from("/_log?ocid="),
-            String::from("&action=confection_send_data&"),
-            String::from("#doc.rust-lang.org"),
-            String::from("||seekingalpha.com/mone_event"),
-            String::from("1035.ietf.org"),
-            String::from("127.0.0.1"),
-            String::from("aac") + ".ccccc".repeat(50).as_str() + ".com",
-            String::from(
-                "rfc---------------------------------------------------------1035.ietf.org",
-            ),
-            String::from("rfc1035-.ietf.org"),
-            String::from("rfc1035.?itf.org"),
-        ];
-        let want = HashSet::new();
-        let have = super::parse(raw.join("\n"));
-        assert_eq!(want, have);
+    fn test_addlist_by_consensus_trust() -> Result<(), String> {
+        let mocks = [
+            ("/consensus-trusted", "rust-lang.org"),
+            ("/consensus-untrusted1", "t.org"),
+            ("/consensus-untrusted2", "t.org\ndocs.rs"),
+        ]
+        .map(|(path, body)| mock("GET", path).with_status(200).with_body(body).create());
+        let url = |path: &str| mockito::server_url() + path;
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            whitelist: None,
+            www_duplication: Some(false),
+            sort: SortOrder::ByConsensus,
+            sources: HashMap::from([(
+                url("/consensus-trusted"),
+                SourceOptions {
+                    trust: 2,
+                    ..Default::default()
+                },
+            )]),
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from(
+                        [
+                            "/consensus-trusted",
+                            "/consensus-untrusted1",
+                            "/consensus-untrusted2",
+                        ]
+                        .map(url),
+                    ),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let have = super::addlist(
+            &config,
+            Arc::new(Whitelist::default()),
+            &Fetcher::new(&config.config),
+        )
+        .unwrap();
+
+        for mock in mocks {
+            mock.assert();
+        }
+        // The trusted source outweighs the two untrusted ones.
+        assert_eq!(vec!["rust-lang.org", "t.org", "docs.rs"], have.list);
         Ok(())
     }
 
     #[test]
-    fn test_parse_truncate() -> Result<(), String> {
-        let raw = vec![
-            String::from("adserver.example.com #example.com - Advertising"),
-            String::from("www.reddit.com/r/learnrust/"),
-            String::from("www.rfc-editor.org."),
-            String::from("www.rust-lang.org:443"),
-        ];
-        let want = HashSet::from_iter([
-            String::from("adserver.example.com"),
-            String::from("www.reddit.com"),
-            String::from("www.rfc-editor.org"),
+    fn test_addlist_by_consensus() -> Result<(), String> {
+        // Set up environment
+        let mocks = [
+            ("/consensus1", "t.org\nrust-lang.org"),
+            ("/consensus2", "t.org"),
+            ("/consensus3", "t.org\ndocs.rs"),
+        ]
+        .map(|(path, body)| mock("GET", path).with_status(200).with_body(body).create());
+
+        let url = &mockito::server_url();
+
+        let mut config = Config {
+            prefix: None,
+            suffix: None,
+            whitelist: None,
+            sort: SortOrder::ByConsensus,
+            ..Default::default()
+        };
+        config.addlist = HashMap::from([(
+            "Addlist".to_owned(),
+            AddlistSources {
+                addlist: HashSet::from_iter(
+                    ["/consensus1", "/consensus2", "/consensus3"].map(|path| url.clone() + path),
+                ),
+                ..Default::default()
+            },
+        )]);
+
+        let config = AddlistConfig {
+            name: "Addlist".to_owned(),
+            config: Arc::new(config),
+        };
+
+        let have = super::addlist(
+            &config,
+            Arc::new(Whitelist::default()),
+            &Fetcher::new(&config.config),
+        );
+        let want = Some(Addlist {
+            name: "Addlist".to_owned(),
+            list: vec![
+                "t.org".to_owned(),
+                "www.t.org".to_owned(),
+                "docs.rs".to_owned(),
+                "rust-lang.org".to_owned(),
+                "www.docs.rs".to_owned(),
+                "www.rust-lang.org".to_owned(),
+            ],
+            ..Default::default()
+        });
+
+        for mock in mocks {
+            mock.assert();
+        }
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlists_by_source() -> Result<(), String> {
+        // Set up environment
+        let mock = mock("GET", "/split")
+            .with_status(200)
+            .with_body("docs.rs\nt.org")
+            .expect(2)
+            .create();
+
+        let url = &mockito::server_url();
+
+        let mut config = Config {
+            prefix: None,
+            suffix: None,
+            whitelist: None,
+            ..Default::default()
+        };
+        config.addlist = HashMap::from([(
+            "Addlist".to_owned(),
+            AddlistSources {
+                addlist: HashSet::from_iter(vec![url.clone() + "/split"]),
+                ..Default::default()
+            },
+        )]);
+
+        let config = AddlistConfig {
+            name: "Addlist".to_owned(),
+            config: Arc::new(config),
+        };
+        let whitelist = Arc::new(Whitelist::from(HashSet::from_iter(vec![
+            "docs.rs".to_owned()
+        ])));
+        let fetcher = Fetcher::new(&config.config);
+
+        let name = format!("Addlist-{}", Url::parse(url).unwrap().host_str().unwrap());
+        let have = super::addlists_by_source(
+            &config,
+            SplitBySource::PreWhitelist,
+            whitelist.clone(),
+            &fetcher,
+        );
+        let want = Some(vec![Addlist {
+            name: name.clone(),
+            list: vec![
+                "docs.rs".to_owned(),
+                "t.org".to_owned(),
+                "www.docs.rs".to_owned(),
+                "www.t.org".to_owned(),
+            ],
+            ..Default::default()
+        }]);
+        assert_eq!(want, have);
+
+        let have =
+            super::addlists_by_source(&config, SplitBySource::PostWhitelist, whitelist, &fetcher);
+        let want = Some(vec![Addlist {
+            name,
+            list: vec!["t.org".to_owned(), "www.t.org".to_owned()],
+            ..Default::default()
+        }]);
+        assert_eq!(want, have);
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_www_whitelist() -> Result<(), String> {
+        // Set up environment
+        let mock = mock("GET", "/www")
+            .with_status(200)
+            .with_body("www.allowed.com\nblocked.com\nwww-allowed.org")
+            .create();
+
+        let url = &mockito::server_url();
+
+        let mut config = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        config.addlist = HashMap::from([(
+            "Addlist".to_owned(),
+            AddlistSources {
+                addlist: HashSet::from_iter(vec![url.clone() + "/www"]),
+                ..Default::default()
+            },
+        )]);
+
+        let config = AddlistConfig {
+            name: "Addlist".to_owned(),
+            config: Arc::new(config),
+        };
+        let whitelist = Arc::new(Whitelist::from(HashSet::from_iter(vec![
+            "allowed.com".to_owned(),
+            "www.www-allowed.org".to_owned(),
+        ])));
+
+        let have = super::addlist(&config, whitelist, &Fetcher::new(&config.config));
+        let want = Some(Addlist {
+            name: "Addlist".to_owned(),
+            list: vec![
+                "blocked.com".to_owned(),
+                "www-allowed.org".to_owned(),
+                "www.allowed.com".to_owned(),
+                "www.blocked.com".to_owned(),
+            ],
+            ..Default::default()
+        });
+
+        mock.assert();
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_without_global_whitelist() -> Result<(), String> {
+        // Set up environment
+        let mock1 = mock("GET", "/no-global-addlist")
+            .with_status(200)
+            .with_body("docs.rs\nwww.rust-lang.org\nt.org")
+            .create();
+        let mock2 = mock("GET", "/no-global-whitelist")
+            .with_status(200)
+            .with_body("t.org")
+            .create();
+
+        let url = &mockito::server_url();
+
+        let mut config = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        config.addlist = HashMap::from([(
+            "Addlist".to_owned(),
+            AddlistSources {
+                addlist: HashSet::from_iter(vec![url.clone() + "/no-global-addlist"]),
+                whitelist: Some(HashSet::from_iter(vec![
+                    url.clone() + "/no-global-whitelist",
+                ])),
+                apply_global_whitelist: Some(false),
+                ..Default::default()
+            },
+        )]);
+
+        let config = AddlistConfig {
+            name: "Addlist".to_owned(),
+            config: Arc::new(config),
+        };
+
+        let whitelist = Arc::new(Whitelist::from(HashSet::from_iter(vec![
+            "docs.rs".to_owned(),
+            "t.org".to_owned(),
+        ])));
+
+        let have = super::addlist(&config, whitelist, &Fetcher::new(&config.config));
+        let want = Some(Addlist {
+            name: "Addlist".to_owned(),
+            list: vec![
+                "docs.rs".to_owned(),
+                "rust-lang.org".to_owned(),
+                "www.docs.rs".to_owned(),
+                "www.rust-lang.org".to_owned(),
+            ],
+            ..Default::default()
+        });
+
+        mock1.assert();
+        mock2.assert();
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_confusable_whitelist() -> Result<(), String> {
+        // Set up environment
+        // The first `e` of the second domain is the cyrillic small letter ie.
+        let mock = mock("GET", "/confusable")
+            .with_status(200)
+            .with_body("docs.rs\nexample.com\n\u{435}xample.com")
+            .create();
+
+        let url = &mockito::server_url();
+
+        let mut config = Config {
+            prefix: None,
+            suffix: None,
+            confusable_whitelist: true,
+            ..Default::default()
+        };
+
+        let mut addlist = HashMap::new();
+        addlist.insert(
+            "Addlist".to_owned(),
+            AddlistSources {
+                addlist: HashSet::from_iter(vec![url.clone() + "/confusable"]),
+                whitelist: None,
+                ..Default::default()
+            },
+        );
+        config.addlist = addlist;
+
+        let config = AddlistConfig {
+            name: "Addlist".to_owned(),
+            config: Arc::new(config),
+        };
+
+        let whitelist = Arc::new(Whitelist::from(HashSet::from_iter(vec![
+            "example.com".to_owned()
+        ])));
+
+        let have = super::addlist(&config, whitelist, &Fetcher::new(&config.config));
+        let want = Some(Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["docs.rs".to_owned(), "www.docs.rs".to_owned()],
+            ..Default::default()
+        });
+
+        mock.assert();
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_regex_whitelist() -> Result<(), String> {
+        // Set up environment
+        let mock = mock("GET", "/regex")
+            .with_status(200)
+            .with_body("docs.rs\nads.rust-lang.org\nt.org")
+            .create();
+
+        let url = &mockito::server_url();
+
+        let mut config = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+
+        let mut addlist = HashMap::new();
+        addlist.insert(
+            "Addlist".to_owned(),
+            AddlistSources {
+                addlist: HashSet::from_iter(vec![url.clone() + "/regex"]),
+                whitelist: None,
+                ..Default::default()
+            },
+        );
+        config.addlist = addlist;
+
+        let config = AddlistConfig {
+            name: "Addlist".to_owned(),
+            config: Arc::new(config),
+        };
+
+        let whitelist = Arc::new(Whitelist {
+            domains: HashSet::from_iter(vec!["docs.rs".to_owned()]),
+            regexes: vec![regex::Regex::new("rust-lang\\.org$").unwrap()],
+            ..Default::default()
+        });
+
+        let have = super::addlist(&config, whitelist, &Fetcher::new(&config.config));
+        let want = Some(Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["t.org".to_owned(), "www.t.org".to_owned()],
+            ..Default::default()
+        });
+
+        mock.assert();
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_order() -> Result<(), String> {
+        let sources: HashSet<_> = (0..20).map(|num| format!("https://{num}.local")).collect();
+        let sorted = super::order(&Config::default(), &sources);
+        assert!(sorted.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let config = Config {
+            shuffle_sources: true,
+            seed: Some(42),
+            ..Default::default()
+        };
+        let shuffled = super::order(&config, &sources);
+        assert_eq!(shuffled, super::order(&config, &sources));
+        assert_ne!(sorted, shuffled);
+        assert_eq!(
+            sorted,
+            shuffled.iter().copied().sorted().collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_combine_separators() -> Result<(), String> {
+        let addlists = vec![
+            Addlist {
+                name: "Tracking".to_owned(),
+                list: vec!["b.com".to_owned(), "c.com".to_owned()],
+                ..Default::default()
+            },
+            Addlist {
+                name: "Ads".to_owned(),
+                list: vec!["a.com".to_owned(), "c.com".to_owned()],
+                ..Default::default()
+            },
+        ];
+
+        let have = super::combine("Combined", addlists, true);
+        let want = Addlist {
+            name: "Combined".to_owned(),
+            list: vec![
+                "# ---- Ads ----".to_owned(),
+                "a.com".to_owned(),
+                "c.com".to_owned(),
+                "# ---- Tracking ----".to_owned(),
+                "b.com".to_owned(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_separator() -> Result<(), String> {
+        assert_eq!("# ---- Ads ----", super::separator("Ads"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_valid() -> Result<(), String> {
+        let raw = vec![
+            String::from("aa") + ".ccccc".repeat(50).as_str() + ".com",
+            String::from("docs.rs"),
+            String::from("rust-lang.org"),
+            String::from("t.org"),
+            String::from("xn--mller-brombel-rmb4fg.de"),
+        ];
+        let want = HashSet::from_iter(raw.clone());
+        let have = super::parse(raw.join("\n"));
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_normalize_dots() -> Result<(), String> {
+        let raw = String::from("example..com\n.rust-lang.org\n..docs..rs.");
+        assert_eq!(HashSet::new(), super::parse(raw.clone()));
+        let want = HashSet::from_iter([
+            String::from("example.com"),
+            String::from("rust-lang.org"),
+            String::from("docs.rs"),
+        ]);
+        assert_eq!(want, super::parse_with(raw, true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_counted() -> Result<(), String> {
+        let raw = String::from("docs.rs\nt.org # docs.rs\nDocs.rs\ninvalid\nt.org docs.rs");
+        let (domains, entries) = super::parse_counted(raw, false);
+        assert_eq!(2, domains.len());
+        assert_eq!(5, entries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invaid() -> Result<(), String> {
+        let raw = vec![
+            String::from("-analytics/analytics."),
+            String::from("::1"),
+            String::from(".php?action_name="),
+            String::from("/_log?ocid="),
+            String::from("&action=confection_send_data&"),
+            String::from("#doc.rust-lang.org"),
+            String::from("||seekingalpha.com/mone_event"),
+            String::from("1035.ietf.org"),
+            String::from("127.0.0.1"),
+            String::from("aac") + ".ccccc".repeat(50).as_str() + ".com",
+            String::from(
+                "rfc---------------------------------------------------------1035.ietf.org",
+            ),
+            String::from("rfc1035-.ietf.org"),
+            String::from("rfc1035.?itf.org"),
+        ];
+        let want = HashSet::new();
+        let have = super::parse(raw.join("\n"));
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::useless_vec)]
+    fn test_parse_truncate() -> Result<(), String> {
+        let raw = vec![
+            String::from("adserver.example.com #example.com - Advertising"),
+            String::from("www.reddit.com/r/learnrust/"),
+            String::from("www.rfc-editor.org."),
+            String::from("www.rust-lang.org:443"),
+        ];
+        let want = HashSet::from_iter([
+            String::from("adserver.example.com"),
+            String::from("www.reddit.com"),
+            String::from("www.rfc-editor.org"),
             String::from("www.rust-lang.org"),
         ]);
-        let have = super::parse(raw.join("\n"));
+        let have = super::parse(raw.join("\n"));
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::useless_vec)]
+    fn test_parse_punicode() -> Result<(), String> {
+        let raw = vec![String::from("www.müller-büromöbel.de")];
+        let want = HashSet::from_iter([String::from("www.xn--mller-brombel-rmb4fg.de")]);
+        let have = super::parse(raw.join("\n"));
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_add() -> Result<(), String> {
+        let premut = HashSet::from_iter([
+            String::from("a.com"),
+            String::from("b.com"),
+            String::from("c.com"),
+        ]);
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        let addlist_config = super::AddlistConfig {
+            name: String::from("New"),
+            config: Arc::new(config),
+        };
+        let want = vec![
+            String::from("a.com"),
+            String::from("b.com"),
+            String::from("c.com"),
+            String::from("www.a.com"),
+            String::from("www.b.com"),
+            String::from("www.c.com"),
+        ];
+        let have = super::mutate(&addlist_config, premut);
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_remove() -> Result<(), String> {
+        let premut = HashSet::from_iter([
+            String::from("www.a.com"),
+            String::from("www.b.com"),
+            String::from("www.c.com"),
+        ]);
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        let addlist_config = super::AddlistConfig {
+            name: String::from("New"),
+            config: Arc::new(config),
+        };
+        let want = vec![
+            String::from("a.com"),
+            String::from("b.com"),
+            String::from("c.com"),
+            String::from("www.a.com"),
+            String::from("www.b.com"),
+            String::from("www.c.com"),
+        ];
+        let have = super::mutate(&addlist_config, premut);
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_duplicate() -> Result<(), String> {
+        let premut = HashSet::from_iter([
+            String::from("a.com"),
+            String::from("b.com"),
+            String::from("c.com"),
+            String::from("www.a.com"),
+            String::from("www.b.com"),
+            String::from("www.c.com"),
+        ]);
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        let addlist_config = super::AddlistConfig {
+            name: String::from("New"),
+            config: Arc::new(config),
+        };
+        let want = vec![
+            String::from("a.com"),
+            String::from("b.com"),
+            String::from("c.com"),
+            String::from("www.a.com"),
+            String::from("www.b.com"),
+            String::from("www.c.com"),
+        ];
+        let have = super::mutate(&addlist_config, premut);
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_both_idn_forms() -> Result<(), String> {
+        let premut = HashSet::from_iter([String::from("xn--mnchen-3ya.de"), String::from("a.com")]);
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            emit_both_idn_forms: true,
+            ..Default::default()
+        };
+        let addlist_config = super::AddlistConfig {
+            name: String::from("New"),
+            config: Arc::new(config),
+        };
+        let want = vec![
+            String::from("a.com"),
+            String::from("xn--mnchen-3ya.de"),
+            String::from("münchen.de"),
+            String::from("www.a.com"),
+            String::from("www.xn--mnchen-3ya.de"),
+            String::from("www.münchen.de"),
+        ];
+        let have = super::mutate(&addlist_config, premut);
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_both_idn_forms_ascii_only() -> Result<(), String> {
+        let premut = HashSet::from_iter([String::from("xn--mnchen-3ya.de")]);
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            www_duplication: Some(false),
+            emit_both_idn_forms: true,
+            output_ascii_only: true,
+            ..Default::default()
+        };
+        let addlist_config = super::AddlistConfig {
+            name: String::from("New"),
+            config: Arc::new(config),
+        };
+        let have = super::mutate(&addlist_config, premut);
+        assert_eq!(vec![String::from("xn--mnchen-3ya.de")], have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_limit() -> Result<(), String> {
+        let premut = HashSet::from_iter([
+            String::from("c.com"),
+            String::from("a.com"),
+            String::from("www.b.com"),
+        ]);
+        let mut config = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        config.addlist = HashMap::from([(
+            "New".to_owned(),
+            AddlistSources {
+                limit: NonZeroUsize::new(3),
+                ..Default::default()
+            },
+        )]);
+        let addlist_config = super::AddlistConfig {
+            name: String::from("New"),
+            config: Arc::new(config),
+        };
+        let want = vec![
+            String::from("a.com"),
+            String::from("b.com"),
+            String::from("c.com"),
+        ];
+        let have = super::mutate(&addlist_config, premut);
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_ublock() -> Result<(), String> {
+        let premut = HashSet::from_iter([
+            String::from("a.com"),
+            String::from("www.b.com"),
+            String::from("ads.c.com"),
+        ]);
+        let config = Config {
+            output_format: OutputFormat::UblockStatic,
+            prefix: Some("0.0.0.0 ".to_owned()),
+            ..Default::default()
+        };
+        let addlist_config = super::AddlistConfig {
+            name: String::from("New"),
+            config: Arc::new(config),
+        };
+        let want = vec![
+            String::from("||a.com^"),
+            String::from("||ads.c.com^"),
+            String::from("||www.b.com^"),
+        ];
+        let have = super::mutate(&addlist_config, premut);
         assert_eq!(want, have);
         Ok(())
     }
 
     #[test]
-    fn test_parse_punicode() -> Result<(), String> {
-        let raw = vec![String::from("www.müller-büromöbel.de")];
-        let want = HashSet::from_iter([String::from("www.xn--mller-brombel-rmb4fg.de")]);
-        let have = super::parse(raw.join("\n"));
-        assert_eq!(want, have);
+    fn test_addlist_ublock_exceptions() -> Result<(), String> {
+        let mocked = mock("GET", "/addlist-ublock")
+            .with_status(200)
+            .with_body("docs.rs\nrust-lang.org")
+            .create();
+        let config = Config {
+            output_format: OutputFormat::UblockStatic,
+            ublock_exceptions: true,
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([mockito::server_url() + "/addlist-ublock"]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new(&config);
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+        let whitelist = Whitelist {
+            domains: HashSet::from(["rust-lang.org".to_owned()]),
+            ..Default::default()
+        };
+
+        let addlist = super::addlist(&addlist_config, Arc::new(whitelist), &fetcher).unwrap();
+
+        mocked.assert();
+        assert_eq!(vec!["||docs.rs^", "@@||rust-lang.org^"], addlist.list);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cached() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let url = "https://1.example.local";
+
+        let parsed = super::parse_cached(
+            dir,
+            url,
+            "docs.rs\ndocs.rs\n@@||t.org^".to_owned(),
+            false,
+            None,
+        );
+        assert_eq!(HashSet::from(["docs.rs".to_owned()]), parsed.domains);
+        assert_eq!(HashSet::from(["t.org".to_owned()]), parsed.exceptions);
+        assert_eq!(2, parsed.entries);
+
+        // An unchanged body is taken from the cache, so a tampered cache shows up.
+        let file = std::fs::read_dir(dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let cached = std::fs::read_to_string(&file).unwrap();
+        std::fs::write(&file, cached.replace("docs.rs", "crates.io")).unwrap();
+        let parsed = super::parse_cached(
+            dir,
+            url,
+            "docs.rs\ndocs.rs\n@@||t.org^".to_owned(),
+            false,
+            None,
+        );
+        assert_eq!(HashSet::from(["crates.io".to_owned()]), parsed.domains);
+        assert_eq!(HashSet::from(["t.org".to_owned()]), parsed.exceptions);
+
+        let parsed = super::parse_cached(dir, url, "rust-lang.org".to_owned(), false, None);
+        assert_eq!(HashSet::from(["rust-lang.org".to_owned()]), parsed.domains);
+        assert_eq!(1, parsed.entries);
+        let parsed = super::parse_cached(dir, url, "rust-lang.org".to_owned(), true, None);
+        assert_eq!(HashSet::from(["rust-lang.org".to_owned()]), parsed.domains);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cached_shared_dir() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let url = "https://1.example.local";
+        let body = Cached {
+            etag: Some("\"v1\"".to_owned()),
+            last_modified: None,
+            body: "docs.rs".to_owned(),
+        };
+
+        http_cache::save(dir, url, &body).map_err(|err| err.to_string())?;
+        super::parse_cached(dir, url, "docs.rs".to_owned(), false, None);
+        let parsed = super::parse_cached(dir, url, "docs.rs".to_owned(), false, None);
+
+        assert_eq!(HashSet::from(["docs.rs".to_owned()]), parsed.domains);
+        assert_eq!(Some(body), http_cache::load(dir, url));
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_last_modified() -> Result<(), String> {
+        let old = mock("GET", "/last-modified-old")
+            .with_status(200)
+            .with_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .with_body("docs.rs\nt.org")
+            .create();
+        let new = mock("GET", "/last-modified-new")
+            .with_status(200)
+            .with_header("last-modified", "Thu, 22 Oct 2015 07:28:00 GMT")
+            .with_body("t.org\nrust-lang.org")
+            .create();
+        let config = Config {
+            last_modified: true,
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([
+                        mockito::server_url() + "/last-modified-new",
+                        mockito::server_url() + "/last-modified-old",
+                    ]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new(&config);
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let addlist =
+            super::addlist(&addlist_config, Arc::new(Whitelist::default()), &fetcher).unwrap();
+
+        old.assert();
+        new.assert();
+        // Sources are fetched sorted, so t.org is first found in the new source.
+        assert_eq!(
+            std::collections::BTreeMap::from([
+                (
+                    "docs.rs".to_owned(),
+                    "Wed, 21 Oct 2015 07:28:00 GMT".to_owned()
+                ),
+                (
+                    "rust-lang.org".to_owned(),
+                    "Thu, 22 Oct 2015 07:28:00 GMT".to_owned()
+                ),
+                (
+                    "t.org".to_owned(),
+                    "Thu, 22 Oct 2015 07:28:00 GMT".to_owned()
+                ),
+            ]),
+            addlist.last_modified
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_drop_reserved() -> Result<(), String> {
+        let mocked = mock("GET", "/reserved")
+            .with_status(200)
+            .with_body("localhost\nfoo.local\n10.0.0.1\nrouter.home.arpa\ndocs.rs")
+            .expect(2)
+            .create();
+        let mut config = Config {
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([mockito::server_url() + "/reserved"]),
+                    ..Default::default()
+                },
+            )]),
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new(&config);
+        let whitelist = Arc::new(Whitelist::default());
+
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config.clone()));
+        let kept = super::addlist(&addlist_config, whitelist.clone(), &fetcher).unwrap();
+        assert!(kept.list.contains(&"foo.local".to_owned()));
+
+        config.drop_reserved = true;
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+        let dropped = super::addlist(&addlist_config, whitelist, &fetcher).unwrap();
+        assert_eq!(vec!["docs.rs", "www.docs.rs"], dropped.list);
+        mocked.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_always_allow() -> Result<(), String> {
+        // Both addlists reference the source, so it is only fetched once.
+        let mocked = mock("GET", "/always-allow")
+            .with_status(200)
+            .with_body("docs.rs\nrust-lang.org")
+            .expect(1)
+            .create();
+        let url = mockito::server_url() + "/always-allow";
+        let config = Config {
+            always_allow: HashSet::from(["Rust-Lang.org".to_owned()]),
+            addlist: HashMap::from([
+                (
+                    "Global".to_owned(),
+                    AddlistSources {
+                        addlist: HashSet::from([url.clone()]),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "Local".to_owned(),
+                    AddlistSources {
+                        addlist: HashSet::from([url]),
+                        apply_global_whitelist: Some(false),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new(&config);
+        let config = Arc::new(config);
+
+        for name in ["Global", "Local"] {
+            let addlist_config = AddlistConfig::new(name, config.clone());
+            let addlist =
+                super::addlist(&addlist_config, Arc::new(Whitelist::default()), &fetcher).unwrap();
+            assert_eq!(vec!["docs.rs", "www.docs.rs"], addlist.list, "{name}");
+        }
+        mocked.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_timeout() -> Result<(), String> {
+        let fast = mock("GET", "/timeout-fast")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+        let slow = mock("GET", "/timeout-slow")
+            .with_status(200)
+            .with_body_from_fn(|body| {
+                std::thread::sleep(std::time::Duration::from_millis(1000));
+                body.write_all(b"rust-lang.org")
+            })
+            .create();
+        let config = Config {
+            timeout: std::num::NonZeroU64::new(300),
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([
+                        mockito::server_url() + "/timeout-fast",
+                        mockito::server_url() + "/timeout-slow",
+                    ]),
+                    ..Default::default()
+                },
+            )]),
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new(&config);
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let addlist =
+            super::addlist(&addlist_config, Arc::new(Whitelist::default()), &fetcher).unwrap();
+
+        fast.assert();
+        slow.assert();
+        assert_eq!(vec!["docs.rs", "www.docs.rs"], addlist.list);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_last_modified_trust() -> Result<(), String> {
+        let untrusted = mock("GET", "/trust-a")
+            .with_status(200)
+            .with_header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .with_body("t.org")
+            .create();
+        let trusted = mock("GET", "/trust-b")
+            .with_status(200)
+            .with_header("last-modified", "Thu, 22 Oct 2015 07:28:00 GMT")
+            .with_body("t.org")
+            .create();
+        let config = Config {
+            last_modified: true,
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([
+                        mockito::server_url() + "/trust-a",
+                        mockito::server_url() + "/trust-b",
+                    ]),
+                    ..Default::default()
+                },
+            )]),
+            sources: HashMap::from([(
+                mockito::server_url() + "/trust-b",
+                SourceOptions {
+                    trust: 10,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new(&config);
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let addlist =
+            super::addlist(&addlist_config, Arc::new(Whitelist::default()), &fetcher).unwrap();
+
+        untrusted.assert();
+        trusted.assert();
+        // The trusted source is fetched last, but still wins the attribution.
+        assert_eq!(
+            Some("Thu, 22 Oct 2015 07:28:00 GMT"),
+            addlist.last_modified.get("t.org").map(String::as_str)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_failed_sources() -> Result<(), String> {
+        let good = mock("GET", "/failed-sources-good")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+        let missing = mock("GET", "/failed-sources-missing")
+            .with_status(404)
+            .create();
+        let missing_url = mockito::server_url() + "/failed-sources-missing";
+        let config = Config {
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([
+                        mockito::server_url() + "/failed-sources-good",
+                        missing_url.clone(),
+                    ]),
+                    ..Default::default()
+                },
+            )]),
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        let fetcher = Fetcher::new(&config);
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let addlist =
+            super::addlist(&addlist_config, Arc::new(Whitelist::default()), &fetcher).unwrap();
+
+        good.assert();
+        missing.assert();
+        assert_eq!(vec!["docs.rs", "www.docs.rs"], addlist.list);
+        assert_eq!(vec![missing_url], addlist.failed_sources);
         Ok(())
     }
 
     #[test]
-    fn test_mutate_add() -> Result<(), String> {
-        let premut = HashSet::from_iter([
-            String::from("a.com"),
-            String::from("b.com"),
-            String::from("c.com"),
-        ]);
+    fn test_parse_rules_abp() -> Result<(), String> {
+        let raw = [
+            "[Adblock Plus 2.0]",
+            "! Title: Ads",
+            "! Report issues to example.com",
+            "[Adblock Plus 2.0] docs.rs",
+            "||ads.example.com^",
+            "||tracker.example.com^$important",
+            "@@||good.example.com^",
+            "example.com##.banner",
+            "##.ad",
+            "||example.com/ads/*^",
+            "||*.example.com^",
+            "/banner[0-9]+/",
+            "0.0.0.0 hosts.example.com # comment ## still a comment",
+        ];
+
+        let parsed = super::parse_rules(raw.join("\n"), false);
+
+        assert_eq!(
+            HashSet::from([
+                "ads.example.com".to_owned(),
+                "tracker.example.com".to_owned(),
+                "hosts.example.com".to_owned(),
+            ]),
+            parsed.domains
+        );
+        assert_eq!(
+            HashSet::from(["good.example.com".to_owned()]),
+            parsed.exceptions
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_abp_exception() -> Result<(), String> {
+        let blocking = mock("GET", "/abp-block")
+            .with_status(200)
+            .with_body("||docs.rs^\n||rust-lang.org^")
+            .create();
+        let excepting = mock("GET", "/abp-except")
+            .with_status(200)
+            .with_body("@@||rust-lang.org^")
+            .create();
         let config = Config {
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([
+                        mockito::server_url() + "/abp-block",
+                        mockito::server_url() + "/abp-except",
+                    ]),
+                    ..Default::default()
+                },
+            )]),
             prefix: None,
             suffix: None,
             ..Default::default()
         };
-        let addlist_config = super::AddlistConfig {
-            name: String::from("New"),
-            config: Arc::new(config),
+        let fetcher = Fetcher::new(&config);
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let addlist =
+            super::addlist(&addlist_config, Arc::new(Whitelist::default()), &fetcher).unwrap();
+
+        blocking.assert();
+        excepting.assert();
+        assert_eq!(vec!["docs.rs", "www.docs.rs"], addlist.list);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_plain() -> Result<(), String> {
+        let config = Config {
+            output_format: OutputFormat::Plain,
+            ..Default::default()
         };
-        let want = vec![
+        let addlist_config = AddlistConfig::new("New", Arc::new(config));
+        let have = super::mutate(&addlist_config, HashSet::from(["a.com".to_owned()]));
+        assert_eq!(vec!["a.com", "www.a.com"], have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_www_duplication() -> Result<(), String> {
+        let premut = HashSet::from_iter([
             String::from("a.com"),
-            String::from("b.com"),
-            String::from("c.com"),
-            String::from("www.a.com"),
             String::from("www.b.com"),
-            String::from("www.c.com"),
+            String::from("c.com"),
+        ]);
+        for (www_duplication, want) in [
+            (
+                Some(true),
+                vec![
+                    "a.com",
+                    "b.com",
+                    "c.com",
+                    "www.a.com",
+                    "www.b.com",
+                    "www.c.com",
+                ],
+            ),
+            (Some(false), vec!["a.com", "c.com", "www.b.com"]),
+        ] {
+            let config = Config {
+                prefix: None,
+                suffix: None,
+                www_duplication,
+                ..Default::default()
+            };
+            let addlist_config = super::AddlistConfig {
+                name: String::from("New"),
+                config: Arc::new(config),
+            };
+            let have = super::mutate(&addlist_config, premut.clone());
+            assert_eq!(want, have, "{www_duplication:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedupe() -> Result<(), String> {
+        let addlists = vec![
+            Addlist {
+                name: "Tracking".to_owned(),
+                list: vec!["b.com".to_owned(), "c.com".to_owned()],
+                ..Default::default()
+            },
+            Addlist {
+                name: "Ads".to_owned(),
+                list: vec!["a.com".to_owned(), "c.com".to_owned()],
+                ..Default::default()
+            },
+        ];
+
+        let have = super::dedupe(addlists);
+        let want = vec![
+            Addlist {
+                name: "Ads".to_owned(),
+                list: vec!["a.com".to_owned(), "c.com".to_owned()],
+                ..Default::default()
+            },
+            Addlist {
+                name: "Tracking".to_owned(),
+                list: vec!["b.com".to_owned()],
+                ..Default::default()
+            },
         ];
-        let have = super::mutate(&addlist_config, premut);
         assert_eq!(want, have);
         Ok(())
     }
 
     #[test]
-    fn test_mutate_remove() -> Result<(), String> {
-        let premut = HashSet::from_iter([
-            String::from("www.a.com"),
-            String::from("www.b.com"),
-            String::from("www.c.com"),
-        ]);
+    fn test_addlist_local_source() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("curated");
+        fs::write(&path, "0.0.0.0 docs.rs").unwrap();
         let config = Config {
             prefix: None,
             suffix: None,
+            size: None,
+            www_duplication: Some(false),
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([
+                        format!("file://{}", path.to_str().unwrap()),
+                        format!("{}/missing", dir.path().to_str().unwrap()),
+                    ]),
+                    ..Default::default()
+                },
+            )]),
             ..Default::default()
         };
-        let addlist_config = super::AddlistConfig {
-            name: String::from("New"),
-            config: Arc::new(config),
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let have = super::addlist(
+            &addlist_config,
+            Arc::new(Whitelist::default()),
+            &Fetcher::new(&addlist_config.config),
+        )
+        .unwrap();
+
+        assert_eq!(vec!["docs.rs".to_owned()], have.list);
+        assert_eq!(1, have.failed_sources.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_regexes() -> Result<(), String> {
+        let have = super::parse_regexes(
+            "# Pi-hole regexes\n/^ad[0-9]+\\./\n(\\.|^)tracker\\.com$\n/^(unclosed/\n",
+        );
+        let want = HashSet::from([
+            "^ad[0-9]+\\.".to_owned(),
+            "(\\.|^)tracker\\.com$".to_owned(),
+        ]);
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_regex() -> Result<(), String> {
+        let mock = mock("GET", "/regex")
+            .with_status(200)
+            .with_body("/^ad[0-9]+\\./\n[invalid\n")
+            .create();
+        let config = Config {
+            addlist: HashMap::from([(
+                "Regex".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([mockito::server_url() + "/regex"]),
+                    kind: SourceKind::Regex,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
         };
-        let want = vec![
-            String::from("a.com"),
-            String::from("b.com"),
-            String::from("c.com"),
-            String::from("www.a.com"),
-            String::from("www.b.com"),
-            String::from("www.c.com"),
-        ];
-        let have = super::mutate(&addlist_config, premut);
+        let addlist_config = AddlistConfig::new("Regex", Arc::new(config));
+
+        let have = super::addlist(
+            &addlist_config,
+            Arc::new(Whitelist::default()),
+            &Fetcher::new(&addlist_config.config),
+        )
+        .unwrap();
+
+        mock.assert();
+        assert_eq!(vec!["^ad[0-9]+\\.".to_owned()], have.list);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_max_domains() -> Result<(), String> {
+        let body = (0..100).map(|num| format!("d{num}.docs.rs")).join("\n");
+        let mock = mock("GET", "/max-domains")
+            .with_status(200)
+            .with_body(body)
+            .create();
+        let config = Config {
+            www_duplication: Some(false),
+            max_domains: NonZeroUsize::new(10),
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([mockito::server_url() + "/max-domains"]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let have = super::addlist(
+            &addlist_config,
+            Arc::new(Whitelist::default()),
+            &Fetcher::new(&addlist_config.config),
+        )
+        .unwrap();
+
+        mock.assert();
+        assert_eq!(10, have.list.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rules_capped() -> Result<(), String> {
+        let raw = (0..100).map(|num| format!("d{num}.docs.rs")).join("\n");
+
+        let parsed = super::parse_rules_capped(format!("d0.docs.rs\n{raw}"), false, Some(10));
+
+        // Parsing stopped at the first domain beyond the maximum.
+        assert!(parsed.capped);
+        assert_eq!(10, parsed.domains.len());
+        assert_eq!(11, parsed.entries);
+        assert!(!super::parse_rules_capped(raw, false, Some(100)).capped);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_json() -> Result<(), String> {
+        let raw = r#"["docs.rs", "rust-lang.org", "not a domain"]"#.to_owned();
+        let have = super::parse(super::extract(raw, SourceFormat::Json, 0)?);
+        let want = HashSet::from(["docs.rs".to_owned(), "rust-lang.org".to_owned()]);
         assert_eq!(want, have);
+
+        assert!(super::extract("{}".to_owned(), SourceFormat::Json, 0).is_err());
         Ok(())
     }
 
     #[test]
-    fn test_mutate_duplicate() -> Result<(), String> {
-        let premut = HashSet::from_iter([
-            String::from("a.com"),
-            String::from("b.com"),
-            String::from("c.com"),
-            String::from("www.a.com"),
-            String::from("www.b.com"),
-            String::from("www.c.com"),
-        ]);
+    fn test_extract_csv() -> Result<(), String> {
+        let raw = "id,domain\n1,docs.rs\n2,\"rust-lang.org\"\n3\n".to_owned();
+        let have = super::parse(super::extract(raw, SourceFormat::Csv, 1)?);
+        let want = HashSet::from(["docs.rs".to_owned(), "rust-lang.org".to_owned()]);
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_blacklist() -> Result<(), String> {
+        let mock = mock("GET", "/blacklist")
+            .with_status(200)
+            .with_body("docs.rs\nrust-lang.org")
+            .create();
         let config = Config {
             prefix: None,
             suffix: None,
+            www_duplication: Some(false),
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([mockito::server_url() + "/blacklist"]),
+                    whitelist: Some(HashSet::from([
+                        "docs.rs".to_owned(),
+                        "rust-lang.org".to_owned(),
+                    ])),
+                    blacklist: Some(HashSet::from(["docs.rs".to_owned(), "t.org".to_owned()])),
+                    ..Default::default()
+                },
+            )]),
             ..Default::default()
         };
-        let addlist_config = super::AddlistConfig {
-            name: String::from("New"),
-            config: Arc::new(config),
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let have = super::addlist(
+            &addlist_config,
+            Arc::new(Whitelist::default()),
+            &Fetcher::new(&addlist_config.config),
+        )
+        .unwrap();
+
+        mock.assert();
+        assert_eq!(vec!["docs.rs".to_owned(), "t.org".to_owned()], have.list);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ips() -> Result<(), String> {
+        let have = super::parse_ips(
+            "# IP list\n192.0.2.1\n2001:db8:0:0:0:0:0:1 # documentation\nnot an address\n10.0.0.0/8\n",
+        );
+        let want = HashSet::from(["192.0.2.1".to_owned(), "2001:db8::1".to_owned()]);
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_fetch_concurrency() -> Result<(), String> {
+        let mocks = ["docs.rs", "crates.io", "rust-lang.org"]
+            .iter()
+            .enumerate()
+            .map(|(num, domain)| {
+                mock("GET", format!("/concurrency-{num}").as_str())
+                    .with_status(200)
+                    .with_body(domain)
+                    .create()
+            })
+            .collect_vec();
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            www_duplication: Some(false),
+            fetch_concurrency: NonZeroUsize::new(2),
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: (0..3)
+                        .map(|num| format!("{}/concurrency-{num}", mockito::server_url()))
+                        .collect(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
         };
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let have = super::addlist(
+            &addlist_config,
+            Arc::new(Whitelist::default()),
+            &Fetcher::new(&addlist_config.config),
+        )
+        .unwrap();
+
+        for mock in mocks {
+            mock.assert();
+        }
         let want = vec![
-            String::from("a.com"),
-            String::from("b.com"),
-            String::from("c.com"),
-            String::from("www.a.com"),
-            String::from("www.b.com"),
-            String::from("www.c.com"),
+            "crates.io".to_owned(),
+            "docs.rs".to_owned(),
+            "rust-lang.org".to_owned(),
         ];
-        let have = super::mutate(&addlist_config, premut);
-        assert_eq!(want, have);
+        assert_eq!(want, have.list);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_suffix_whitelist() -> Result<(), String> {
+        let _mock = mock("GET", "/suffix-whitelist")
+            .with_status(200)
+            .with_body("docs.rs\nads.example.com\nexample.com\nprinter.local\nt.org")
+            .create();
+        let url = mockito::server_url() + "/suffix-whitelist";
+        let mut config = Config {
+            prefix: None,
+            suffix: None,
+            www_duplication: Some(false),
+            ..Default::default()
+        };
+        config.addlist = HashMap::from([(
+            "Addlist".to_owned(),
+            AddlistSources {
+                addlist: HashSet::from([url]),
+                whitelist: Some(HashSet::from([
+                    "docs.rs".to_owned(),
+                    "*.example.com".to_owned(),
+                    ".local".to_owned(),
+                ])),
+                ..Default::default()
+            },
+        )]);
+        let config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let have = super::addlist(
+            &config,
+            Arc::new(Whitelist::default()),
+            &Fetcher::new(&config.config),
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec!["example.com".to_owned(), "t.org".to_owned()],
+            have.list
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_offline() -> Result<(), String> {
+        let remote = mock("GET", "/offline")
+            .with_body("crates.io")
+            .expect(0)
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("synced");
+        fs::write(&path, "docs.rs\nt.org").unwrap();
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            size: None,
+            www_duplication: Some(false),
+            offline: true,
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([
+                        format!("file://{}", path.to_str().unwrap()),
+                        mockito::server_url() + "/offline",
+                    ]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+        let fetcher = Fetcher::new(&addlist_config.config);
+
+        let have =
+            super::addlist(&addlist_config, Arc::new(Whitelist::default()), &fetcher).unwrap();
+
+        remote.assert();
+        assert_eq!(vec!["docs.rs".to_owned(), "t.org".to_owned()], have.list);
+        // Skipped sources did not fail.
+        assert!(have.failed_sources.is_empty());
+        assert_eq!((1, 0), fetcher.outcomes());
+        assert_eq!(vec![mockito::server_url() + "/offline"], fetcher.skipped());
+        assert_eq!(
+            Err("skipped offline".to_owned()),
+            fetcher.probe(&(mockito::server_url() + "/offline"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_multi_label_suffix() -> Result<(), String> {
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        let config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let have = super::mutate(&config, HashSet::from(["example.co.uk".to_owned()]));
+        assert_eq!(vec!["example.co.uk", "www.example.co.uk"], have);
+
+        let have = super::mutate(&config, HashSet::from(["www.example.co.uk".to_owned()]));
+        assert_eq!(vec!["example.co.uk", "www.example.co.uk"], have);
+
+        let have = super::mutate(&config, HashSet::from(["ads.example.co.uk".to_owned()]));
+        assert_eq!(vec!["ads.example.co.uk"], have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlists_by_source_max_domains() -> Result<(), String> {
+        let body = (0..100).map(|num| format!("d{num}.docs.rs")).join("\n");
+        let first = mock("GET", "/by-source-max-1")
+            .with_status(200)
+            .with_body(&body)
+            .create();
+        let second = mock("GET", "/by-source-max-2")
+            .with_status(200)
+            .with_body(&body)
+            .expect(0)
+            .create();
+        let config = Config {
+            www_duplication: Some(false),
+            max_domains: NonZeroUsize::new(10),
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([
+                        mockito::server_url() + "/by-source-max-1",
+                        mockito::server_url() + "/by-source-max-2",
+                    ]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let addlist_config = AddlistConfig::new("Addlist", Arc::new(config));
+
+        let have = super::addlists_by_source(
+            &addlist_config,
+            SplitBySource::PreWhitelist,
+            Arc::new(Whitelist::default()),
+            &Fetcher::new(&addlist_config.config),
+        )
+        .unwrap();
+
+        first.assert();
+        second.assert();
+        assert_eq!(
+            10,
+            have.iter().map(|addlist| addlist.list.len()).sum::<usize>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lines() -> Result<(), String> {
+        let mut lines = (0..100).map(|num| format!("d{num}.docs.rs"));
+
+        let parsed = super::parse_lines(&mut lines, false, Some(10));
+
+        assert!(parsed.capped);
+        assert_eq!(10, parsed.domains.len());
+        // No line is taken after the one beyond the maximum.
+        assert_eq!(Some("d11.docs.rs".to_owned()), lines.next());
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_confusable_whitelist_hit() -> Result<(), String> {
+        // The first `e` is the cyrillic small letter ie.
+        let mock = mock("GET", "/confusable-hit")
+            .with_status(200)
+            .with_body("docs.rs\n\u{435}xample.com")
+            .create();
+        let config = Config {
+            prefix: None,
+            suffix: None,
+            confusable_whitelist: true,
+            audit: true,
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([mockito::server_url() + "/confusable-hit"]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let config = AddlistConfig::new("Addlist", Arc::new(config));
+        let whitelist = Arc::new(Whitelist::from(HashSet::from([
+            "example.com".to_owned(),
+            "unused.com".to_owned(),
+        ])));
+
+        let have =
+            super::addlist(&config, whitelist.clone(), &Fetcher::new(&config.config)).unwrap();
+
+        mock.assert();
+        assert_eq!(1, have.removed.len());
+        assert_eq!(vec!["unused.com".to_owned()], whitelist.unused());
         Ok(())
     }
 }
@@ -1,2 +1,9 @@
+mod breaker;
+mod cache;
+mod cname;
+pub mod fetch;
+mod http_cache;
 pub mod lists;
-mod validation;
+pub mod pihole;
+mod robots;
+pub mod validation;
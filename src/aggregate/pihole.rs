@@ -0,0 +1,131 @@
+use crate::aggregate::validation;
+use crate::data::Whitelist;
+use anyhow::{anyhow, Error};
+use regex::Regex;
+use std::fs;
+
+const COMMENT: char = '#';
+const DB_EXTENSION: &str = ".db";
+
+/// Reads a Pi-hole allowlist.
+///
+/// A path ending with `.db` is read as Pi-hole `gravity.db`, where the enabled exact and regex allow entries are used.
+/// Any other file contains one entry per line. Valid domains are exact entries, everything else is a regex.
+///
+/// # Errors
+/// - If the allowlist could not be read.
+/// - If a regex is invalid.
+pub fn allowlist(path: &str) -> Result<Whitelist, Error> {
+    if path.ends_with(DB_EXTENSION) {
+        return allowlist_db(path);
+    }
+
+    let raw = fs::read_to_string(path)
+        .map_err(|err| anyhow!("Failed to read Pi-hole allowlist `{path}`: {err}"))?;
+    let mut whitelist = Whitelist::default();
+    for line in raw.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(COMMENT) {
+            continue;
+        }
+        match validation::validate(line) {
+            Some(domain) if domain == line => {
+                whitelist.domains.insert(domain);
+            }
+            _ => whitelist.regexes.push(regex(line)?),
+        }
+    }
+    Ok(whitelist)
+}
+
+fn regex(pattern: &str) -> Result<Regex, Error> {
+    Regex::new(pattern).map_err(|err| anyhow!("Invalid Pi-hole allowlist regex `{pattern}`: {err}"))
+}
+
+#[cfg(feature = "gravity")]
+fn allowlist_db(path: &str) -> Result<Whitelist, Error> {
+    /// `domainlist.type` of exact allow entries.
+    const EXACT: u8 = 0;
+    /// `domainlist.type` of regex allow entries.
+    const REGEX: u8 = 2;
+
+    let connection =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = connection
+        .prepare("SELECT domain, type FROM domainlist WHERE enabled = 1 AND type IN (?1, ?2)")?;
+    let entries = statement
+        .query_map([EXACT, REGEX], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, u8)>, _>>()?;
+
+    let mut whitelist = Whitelist::default();
+    for (domain, kind) in entries {
+        if kind == REGEX {
+            whitelist.regexes.push(regex(&domain)?);
+        } else {
+            whitelist.domains.insert(domain);
+        }
+    }
+    Ok(whitelist)
+}
+
+#[cfg(not(feature = "gravity"))]
+fn allowlist_db(path: &str) -> Result<Whitelist, Error> {
+    Err(anyhow!(
+        "Reading the Pi-hole allowlist `{path}` requires the `gravity` feature."
+    ))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn test_allowlist_text() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowlist.txt");
+        fs::write(&path, "# Allowed\ndocs.rs\n(\\.|^)rust-lang\\.org$\n").unwrap();
+
+        let have = super::allowlist(path.to_str().unwrap()).map_err(|err| err.to_string())?;
+
+        assert!(have.contains("docs.rs"));
+        assert!(have.contains("www.rust-lang.org"));
+        assert!(!have.contains("www.docs.rs"));
+        assert_eq!(1, have.regexes.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_allowlist_invalid_regex() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("allowlist.txt");
+        fs::write(&path, "(rust-lang\n").unwrap();
+
+        assert!(super::allowlist(path.to_str().unwrap()).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "gravity")]
+    #[test]
+    fn test_allowlist_db() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gravity.db");
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE domainlist (domain TEXT, type INTEGER, enabled BOOLEAN);
+                 INSERT INTO domainlist VALUES ('docs.rs', 0, 1);
+                 INSERT INTO domainlist VALUES ('ads.docs.rs', 0, 0);
+                 INSERT INTO domainlist VALUES ('tracker.org', 1, 1);
+                 INSERT INTO domainlist VALUES ('rust-lang\\.org$', 2, 1);",
+            )
+            .unwrap();
+
+        let have = super::allowlist(path.to_str().unwrap()).map_err(|err| err.to_string())?;
+
+        assert!(have.contains("docs.rs"));
+        assert!(have.contains("www.rust-lang.org"));
+        assert!(!have.contains("ads.docs.rs"));
+        assert!(!have.contains("tracker.org"));
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+/// User-Agent sent when fetching sources and matched against robots.txt groups.
+pub const USER_AGENT: &str = concat!("phda/", env!("CARGO_PKG_VERSION"));
+
+/// Product token of the [USER_AGENT] used to find the matching robots.txt group.
+const PRODUCT: &str = "phda";
+
+/// Rules of a robots.txt that apply to this program.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Robots {
+    /// Path prefixes that may be fetched.
+    allow: Vec<String>,
+    /// Path prefixes that must not be fetched.
+    disallow: Vec<String>,
+}
+
+impl Robots {
+    /// Parses the rules of the group matching this program, or of the `*` group if none matches.
+    pub fn parse(body: &str) -> Robots {
+        let mut specific = None;
+        let mut wildcard = None;
+        // User-agents of the current group and whether rules were already read for it.
+        let mut agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+        let mut rules = Robots::default();
+
+        let mut finish = |agents: &[String], rules: Robots| {
+            if agents.iter().any(|agent| agent.contains(PRODUCT)) {
+                specific.get_or_insert(rules);
+            } else if agents.iter().any(|agent| agent == "*") {
+                wildcard.get_or_insert(rules);
+            }
+        };
+
+        for line in body.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim().to_lowercase().as_str() {
+                "user-agent" => {
+                    if in_rules {
+                        finish(&agents, std::mem::take(&mut rules));
+                        agents.clear();
+                        in_rules = false;
+                    }
+                    agents.push(value.to_lowercase());
+                }
+                "allow" if !value.is_empty() => {
+                    in_rules = true;
+                    rules.allow.push(value.to_owned());
+                }
+                "disallow" => {
+                    in_rules = true;
+                    if !value.is_empty() {
+                        rules.disallow.push(value.to_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+        finish(&agents, rules);
+        specific.or(wildcard).unwrap_or_default()
+    }
+
+    /// Whether the path may be fetched.
+    ///
+    /// The longest matching prefix decides, an allow wins on equal length.
+    pub fn allows(&self, path: &str) -> bool {
+        let longest = |prefixes: &[String]| {
+            prefixes
+                .iter()
+                .filter(|prefix| path.starts_with(prefix.as_str()))
+                .map(String::len)
+                .max()
+        };
+        match (longest(&self.allow), longest(&self.disallow)) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(allow), Some(disallow)) => allow >= disallow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Robots;
+
+    #[test]
+    fn test_parse_group() -> Result<(), String> {
+        let robots = Robots::parse(
+            "User-agent: *\nDisallow: /\n\nUser-agent: PHDA\nDisallow: /private # no lists\nAllow: /private/public",
+        );
+        assert!(robots.allows("/lists/hosts"));
+        assert!(!robots.allows("/private/hosts"));
+        assert!(robots.allows("/private/public/hosts"));
+
+        let robots = Robots::parse("User-agent: other\nDisallow: /\n\nUser-agent: *\nDisallow:");
+        assert!(robots.allows("/hosts"));
+        Ok(())
+    }
+}
@@ -1,15 +1,84 @@
 use crate::aggregate::lists::DOT;
+use log::warn;
+use publicsuffix::{List, Psl};
+use std::fmt;
+use std::net::IpAddr;
 use std::num::NonZeroUsize;
+use std::sync::OnceLock;
 
 const HYPHEN: char = '-';
 const PUNY: &str = "xn--";
 const VALID_CHARS: [char; 2] = [HYPHEN, DOT];
 
+/// Special-use names of rfc6761, rfc6762 and rfc8375 and the `internal` TLD, which are never public.
+const RESERVED: [&str; 10] = [
+    "test",
+    "localhost",
+    "invalid",
+    "example",
+    "example.com",
+    "example.net",
+    "example.org",
+    "local",
+    "home.arpa",
+    "internal",
+];
+
+/// Snapshot of the [Public Suffix List](https://publicsuffix.org/list/public_suffix_list.dat),
+/// see the README on how to update it.
+const PUBLIC_SUFFIX_LIST: &str = include_str!("../../assets/public_suffix_list.dat");
+
+/// Rule of rfc1035 a domain violates.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Rejection {
+    NoDot,
+    EmptyLabel,
+    FirstNotLetter,
+    LastNotLetterOrDigit,
+    InvalidCharacter,
+    LabelTooLong,
+    DomainTooLong,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Rejection::NoDot => "the domain does not contain a dot",
+            Rejection::EmptyLabel => "at least one label is empty",
+            Rejection::FirstNotLetter => "at least one label does not start with a letter",
+            Rejection::LastNotLetterOrDigit => {
+                "at least one label does not end with a letter or a digit"
+            }
+            Rejection::InvalidCharacter => {
+                "at least one label contains characters other than letters, digits or hyphens"
+            }
+            Rejection::LabelTooLong => "at least one label is longer than 63 characters",
+            Rejection::DomainTooLong => "the domain is longer than 255 characters",
+        };
+        f.write_str(reason)
+    }
+}
+
 /// Validates domain as in rfc1035 defined.
 pub fn validate(domain: &str) -> Option<String> {
+    check(domain).ok()
+}
+
+/// Validates domain as in rfc1035 defined and explains why it is rejected.
+///
+/// Returns the normalized domain, which is converted to punicode and truncated.
+/// Only a single trailing dot of a fully qualified domain is stripped, any other leading,
+/// trailing or consecutive dots are rejected as empty labels unless [`normalize_dots`] is applied first.
+pub fn check(domain: &str) -> Result<String, Rejection> {
     let domain = truncate(encode(domain));
-    let mut lables = domain.split(DOT);
+    let lables = domain.split(DOT);
 
+    if !domain.contains(DOT) {
+        return Err(Rejection::NoDot);
+    }
+    if !lables.clone().all(|label| !label.is_empty()) {
+        return Err(Rejection::EmptyLabel);
+    }
     let is_first_alphabetic = lables.clone().all(|label| {
         label
             .chars()
@@ -17,35 +86,92 @@ pub fn validate(domain: &str) -> Option<String> {
             .map(|c| c.is_ascii_alphabetic())
             .unwrap_or_else(|| false)
     });
+    if !is_first_alphabetic {
+        return Err(Rejection::FirstNotLetter);
+    }
     let is_last_alphanumeric = lables.clone().all(|label| {
         label
             .chars()
             .last()
-            .map(|c| (c.is_ascii_alphanumeric()))
+            .map(|c| c.is_ascii_alphanumeric())
             .unwrap_or_else(|| false)
     });
+    if !is_last_alphanumeric {
+        return Err(Rejection::LastNotLetterOrDigit);
+    }
     let is_interior_characters_valid = lables
         .clone()
         .all(|label| label.chars().all(|c| c.is_alphanumeric() || HYPHEN.eq(&c)));
-    let upper_limit = lables.clone().all(|label| label.len() <= 63);
-    let lower_limit = lables.all(|label| !label.is_empty());
-    let total_upper_limit = domain.chars().filter(|c| !DOT.eq(c)).count() <= 255;
-    let contains_dot = domain.contains(DOT);
-
-    if contains_dot
-        && is_first_alphabetic
-        && is_last_alphanumeric
-        && is_interior_characters_valid
-        && upper_limit
-        && lower_limit
-        && total_upper_limit
-    {
-        Some(domain)
-    } else {
-        None
+    if !is_interior_characters_valid {
+        return Err(Rejection::InvalidCharacter);
+    }
+    if !lables.clone().all(|label| label.len() <= 63) {
+        return Err(Rejection::LabelTooLong);
+    }
+    if domain.chars().filter(|c| !DOT.eq(c)).count() > 255 {
+        return Err(Rejection::DomainTooLong);
+    }
+    Ok(domain)
+}
+
+/// Whether the entry is a reserved name or subdomain of it, or a private, loopback or link-local IP.
+pub fn is_reserved(entry: &str) -> bool {
+    let entry = entry.trim_end_matches(DOT);
+    if let Ok(ip) = entry.parse::<IpAddr>() {
+        return match ip {
+            IpAddr::V4(ip) => {
+                ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+            }
+            // Unique local addresses are fc00::/7 and link-local ones fe80::/10.
+            IpAddr::V6(ip) => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    || (ip.segments()[0] & 0xfe00) == 0xfc00
+                    || (ip.segments()[0] & 0xffc0) == 0xfe80
+            }
+        };
+    }
+    RESERVED.iter().any(|reserved| {
+        entry == *reserved
+            || entry
+                .strip_suffix(reserved)
+                .is_some_and(|subdomain| subdomain.ends_with(DOT))
+    })
+}
+
+/// Whether the domain is registrable, a single label below its public suffix like `example.co.uk`.
+///
+/// Suffixes not in the Public Suffix List are taken as single label, so `example.test` is registrable as well.
+/// If the bundled list can't be parsed, which is reported once, only two-label domains are registrable.
+pub fn is_registrable(domain: &str) -> bool {
+    static LIST: OnceLock<Option<List>> = OnceLock::new();
+    let list = LIST.get_or_init(|| {
+        PUBLIC_SUFFIX_LIST
+            .parse()
+            .map_err(|err| {
+                warn!("Failed to parse the Public Suffix List, taking two-label domains as registrable: {err}");
+            })
+            .ok()
+    });
+    match list {
+        Some(list) => list
+            .domain(domain.as_bytes())
+            .is_some_and(|registrable| registrable.as_bytes() == domain.as_bytes()),
+        None => domain.split(DOT).count() == 2,
     }
 }
 
+/// Collapses consecutive dots and strips all leading and trailing dots.
+///
+/// `example..com`, `.example.com` and `example.com..` all become `example.com`.
+pub fn normalize_dots(domain: &str) -> String {
+    domain
+        .split(DOT)
+        .filter(|label| !label.is_empty())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
 /// Recives possible IDNs and converts it to punicode if needed.
 fn encode(decoded: &str) -> String {
     decoded
@@ -64,6 +190,29 @@ fn help_encode(decoded: &str) -> String {
         .unwrap_or_else(|_| decoded.to_owned())
 }
 
+/// Converts punicode labels of the domain back to unicode.
+pub fn decode(encoded: &str) -> String {
+    encoded
+        .split(DOT)
+        .map(help_decode)
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+fn help_decode(encoded: &str) -> String {
+    encoded
+        .strip_prefix(PUNY)
+        .and_then(|label| punycode::decode(label).ok())
+        .unwrap_or_else(|| encoded.to_owned())
+}
+
+/// Creates the skeleton of a (punicode) domain as in [UTS #39](https://www.unicode.org/reports/tr39/#def-skeleton) defined.
+///
+/// Domains that are confusable with each other share the same skeleton.
+pub fn skeleton(domain: &str) -> String {
+    unicode_security::skeleton(&decode(domain)).collect()
+}
+
 /// Truncates invalid characters and returns the valid part.
 fn truncate(raw: String) -> String {
     let invalid: String = raw
@@ -86,6 +235,36 @@ fn truncate(raw: String) -> String {
 }
 
 mod tests {
+    #[test]
+    fn test_is_reserved() -> Result<(), String> {
+        for entry in [
+            "localhost",
+            "foo.local",
+            "foo.localhost",
+            "a.b.test",
+            "www.example.com",
+            "router.home.arpa",
+            "10.0.0.1",
+            "192.168.178.1",
+            "127.0.0.1",
+            "::1",
+            "fd00::1",
+        ] {
+            assert!(super::is_reserved(entry), "{entry}");
+        }
+        for entry in [
+            "docs.rs",
+            "notlocal",
+            "example.de",
+            "latest.com",
+            "1.1.1.1",
+            "2606:4700::1111",
+        ] {
+            assert!(!super::is_reserved(entry), "{entry}");
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_decode_no_change() -> Result<(), String> {
         assert_eq!("www.rust-lang.org", super::encode("www.rust-lang.org"));
@@ -101,6 +280,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_decode() -> Result<(), String> {
+        assert_eq!(
+            "www.müller-büromöbel.de",
+            super::decode("www.xn--mller-brombel-rmb4fg.de")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_skeleton_confusable() -> Result<(), String> {
+        // The first `e` is the cyrillic small letter ie.
+        assert_eq!(
+            super::skeleton("example.com"),
+            super::skeleton(&super::encode("\u{435}xample.com"))
+        );
+        assert_ne!(
+            super::skeleton("example.com"),
+            super::skeleton("examples.com")
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_not_truncated() -> Result<(), String> {
         assert_eq!(
@@ -131,6 +333,20 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_normalize_dots_consecutive() -> Result<(), String> {
+        assert_eq!("example.com", super::normalize_dots("example..com"));
+        assert_eq!("a.example.com", super::normalize_dots("a...example.com"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_dots_leading() -> Result<(), String> {
+        assert_eq!("example.com", super::normalize_dots(".example.com"));
+        assert_eq!("example.com", super::normalize_dots("..example.com."));
+        Ok(())
+    }
+
     #[test]
     fn test_validate_valid() -> Result<(), String> {
         assert_eq!(
@@ -192,6 +408,26 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_check_reason() -> Result<(), String> {
+        use super::Rejection;
+        assert_eq!(Err(Rejection::NoDot), super::check("localhost"));
+        assert_eq!(Err(Rejection::EmptyLabel), super::check("docs..rs"));
+        assert_eq!(
+            Err(Rejection::FirstNotLetter),
+            super::check("1035.ietf.org")
+        );
+        assert_eq!(
+            Err(Rejection::LastNotLetterOrDigit),
+            super::check("rfc1035-.ietf.org")
+        );
+        assert_eq!(
+            Err(Rejection::LabelTooLong),
+            super::check(&("a".repeat(64) + ".org"))
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_validate_long() -> Result<(), String> {
         assert_eq!(
@@ -203,4 +439,44 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_check_dots() -> Result<(), String> {
+        assert_eq!(Ok("example.com".to_owned()), super::check("example.com."));
+        for domain in ["example.com..", ".example.com", "example..com"] {
+            assert_eq!(Err(super::Rejection::EmptyLabel), super::check(domain));
+            assert_eq!(
+                Some("example.com".to_owned()),
+                super::validate(&super::normalize_dots(domain))
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_registrable() -> Result<(), String> {
+        assert!(super::is_registrable("docs.rs"));
+        assert!(super::is_registrable("example.co.uk"));
+        assert!(super::is_registrable("example.test"));
+        assert!(!super::is_registrable("co.uk"));
+        assert!(!super::is_registrable("www.example.co.uk"));
+        assert!(!super::is_registrable("www.docs.rs"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_public_suffix_list() -> Result<(), String> {
+        use publicsuffix::{List, Psl};
+
+        // A broken update of the bundled list must not silently fall back to two labels.
+        let list: List = super::PUBLIC_SUFFIX_LIST
+            .parse()
+            .map_err(|err| format!("{err:?}"))?;
+        assert_eq!(
+            Some(&b"co.uk"[..]),
+            list.suffix(b"example.co.uk")
+                .map(|suffix| suffix.as_bytes())
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Error};
+
+/// What the binary is asked to do.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Creates all addlists.
+    Run,
+    /// Validates a single domain without fetching anything.
+    ValidateDomain(String),
+    /// Runs pre-flight checks against the config.
+    SelfTest,
+    /// Checks that no written addlist file is older than allowed.
+    CheckStale,
+    /// Prints the effective config after all merging and overrides.
+    ExplainConfig,
+    /// Rewrites the local config file in canonical form.
+    FormatConfig,
+}
+
+/// Parsed command line arguments.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Args {
+    pub command: Command,
+    pub verbose: bool,
+    /// Path of the local config or URL the config is fetched from.
+    pub config: Option<String>,
+    /// Last known good copy of a remote config.
+    pub config_cache: Option<String>,
+    /// Uses all logical cores regardless of the config.
+    pub all_cores: bool,
+    /// Builds the addlists without writing them.
+    pub dry_run: bool,
+    /// Emits progress events to stdout.
+    pub events: bool,
+}
+
+/// Parses the command line arguments without the binary name.
+///
+/// # Errors
+/// - If an argument is unknown or a value is missing.
+pub fn parse<I>(args: I) -> Result<Args, Error>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut parsed = Args {
+        command: Command::Run,
+        verbose: false,
+        config: None,
+        config_cache: None,
+        all_cores: false,
+        dry_run: false,
+        events: false,
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--validate-domain" => {
+                parsed.command = Command::ValidateDomain(value(&arg, args.next())?);
+            }
+            "--self-test" => parsed.command = Command::SelfTest,
+            "--check-stale" => parsed.command = Command::CheckStale,
+            "--explain-config" => parsed.command = Command::ExplainConfig,
+            "--format-config" => parsed.command = Command::FormatConfig,
+            "--all-cores" => parsed.all_cores = true,
+            "--dry-run" => parsed.dry_run = true,
+            "--events" => parsed.events = true,
+            "--verbose" | "-v" => parsed.verbose = true,
+            "--config" => parsed.config = Some(value(&arg, args.next())?),
+            "--config-cache" => parsed.config_cache = Some(value(&arg, args.next())?),
+            _ => return Err(anyhow!("Unknown argument `{arg}`")),
+        }
+    }
+    Ok(parsed)
+}
+
+fn value(arg: &str, value: Option<String>) -> Result<String, Error> {
+    value.ok_or_else(|| anyhow!("Missing value for `{arg}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Args, Command};
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        super::parse(args.iter().map(|arg| arg.to_string())).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_parse_default() -> Result<(), String> {
+        assert_eq!(
+            Args {
+                command: Command::Run,
+                verbose: false,
+                config: None,
+                config_cache: None,
+                all_cores: false,
+                dry_run: false,
+                events: false,
+            },
+            parse(&[])?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_validate_domain() -> Result<(), String> {
+        assert_eq!(
+            Args {
+                command: Command::ValidateDomain("docs.rs".to_owned()),
+                verbose: true,
+                config: None,
+                config_cache: None,
+                all_cores: false,
+                dry_run: false,
+                events: false,
+            },
+            parse(&["--validate-domain", "docs.rs", "--verbose"])?
+        );
+        assert!(parse(&["--validate-domain"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+        assert_eq!(Command::SelfTest, parse(&["--self-test"])?.command);
+        assert_eq!(Command::CheckStale, parse(&["--check-stale"])?.command);
+        assert_eq!(
+            Command::ExplainConfig,
+            parse(&["--explain-config"])?.command
+        );
+        assert_eq!(Command::FormatConfig, parse(&["--format-config"])?.command);
+        assert!(parse(&["--all-cores"])?.all_cores);
+        assert!(parse(&["--dry-run"])?.dry_run);
+        assert!(parse(&["--events"])?.events);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_remote_config() -> Result<(), String> {
+        let args = parse(&[
+            "--config",
+            "https://config.example.local/config.yml",
+            "--config-cache",
+            "./data/remote.yml",
+        ])?;
+        assert_eq!(
+            Some("https://config.example.local/config.yml".to_owned()),
+            args.config
+        );
+        assert_eq!(Some("./data/remote.yml".to_owned()), args.config_cache);
+        assert_eq!(
+            Some("./data/config.yml".to_owned()),
+            parse(&["--config", "./data/config.yml"])?.config
+        );
+        Ok(())
+    }
+}
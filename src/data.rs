@@ -1,19 +1,260 @@
-use crate::config::Config;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashSet, sync::Arc};
+use crate::aggregate::lists::{COMMENT, DOT, WILDCARD};
+use crate::config::{Config, OutputFormat};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-#[derive(Eq, PartialEq, Debug)]
+#[derive(Eq, PartialEq, Debug, Default)]
 pub struct Addlist {
     pub name: String,
     pub list: Vec<String>,
+    /// `Last-Modified` of the source each domain was first found in, by domain.
+    pub last_modified: BTreeMap<String, String>,
+    /// Sources that could not be fetched, in the order they were fetched.
+    pub failed_sources: Vec<String>,
+    /// Domains of the sources removed by a whitelist or an exception rule, sorted.
+    ///
+    /// Only recorded with [Config].audit.
+    pub removed: Vec<String>,
 }
 
-#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
 pub struct AddlistSources {
     pub addlist: HashSet<String>,
     pub whitelist: Option<HashSet<String>>,
+    /// Maximum number of entries written, the first ones of the sorted addlist are kept.
+    pub limit: Option<NonZeroUsize>,
+    /// Whether the global whitelist applies to the addlist, `true` if not set.
+    pub apply_global_whitelist: Option<bool>,
+    /// Addlists whose sources are included.
+    #[serde(default)]
+    pub extends: Vec<String>,
+    /// Title written into the header of the addlist files with [Config].gravity_headers.
+    pub title: Option<String>,
+    /// Homepage written into the header of the addlist files with [Config].gravity_headers.
+    pub homepage: Option<String>,
+    /// Domains that are always part of the addlist and never whitelisted.
+    ///
+    /// Like the whitelist, entries are either sources or domains.
+    pub blacklist: Option<HashSet<String>>,
+    /// What the sources of the addlist contain.
+    #[serde(default)]
+    pub kind: SourceKind,
+    /// Prefix of the entries of the addlist, overriding [Config].prefix.
+    pub prefix: Option<String>,
+    /// Suffix of the entries of the addlist, overriding [Config].suffix.
+    pub suffix: Option<String>,
+}
+
+/// Content of the sources of an addlist.
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum SourceKind {
+    /// Domains in any of the supported list formats.
+    #[default]
+    Domains,
+    /// One Pi-hole regex per line, optionally enclosed in `/`.
+    ///
+    /// The regexes are written unchanged into `.regex` files.
+    Regex,
+    /// One IPv4 or IPv6 address per line, written into `.ip` files.
+    Ip,
+}
+
+/// An addlist is either defined as a bare list of sources or with all options.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AddlistSourcesRepr {
+    Sources(HashSet<String>),
+    Full(Box<AddlistSources>),
+}
+
+impl From<AddlistSourcesRepr> for AddlistSources {
+    fn from(repr: AddlistSourcesRepr) -> Self {
+        match repr {
+            AddlistSourcesRepr::Sources(addlist) => AddlistSources {
+                addlist,
+                ..Default::default()
+            },
+            AddlistSourcesRepr::Full(sources) => *sources,
+        }
+    }
+}
+
+/// Deserializes the addlists where each addlist may be a bare list of sources.
+pub fn deserialize_addlists<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, AddlistSources>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let addlists: HashMap<String, AddlistSourcesRepr> = HashMap::deserialize(deserializer)?;
+    Ok(addlists
+        .into_iter()
+        .map(|(name, sources)| (name, sources.into()))
+        .collect())
+}
+
+/// Domains that must not be blocked.
+#[derive(Debug, Default)]
+pub struct Whitelist {
+    /// Exact domains.
+    pub domains: HashSet<String>,
+    /// Domains whose subdomains are all whitelisted, listed as `*.example.com` or `.example.com`.
+    pub suffixes: HashSet<String>,
+    /// Patterns a domain is matched against.
+    pub regexes: Vec<Regex>,
+    /// Entries that matched at least one domain.
+    pub hits: Mutex<HashSet<String>>,
+}
+
+impl Whitelist {
+    /// Whether the domain is whitelisted, either exactly, as subdomain of a suffix or by any regex.
+    ///
+    /// The matching entry is recorded as hit.
+    pub fn contains(&self, domain: &str) -> bool {
+        let entry = if self.domains.contains(domain) {
+            Cow::Borrowed(domain)
+        } else if let Some(suffix) = self.suffix(domain) {
+            Cow::Owned(format!("{WILDCARD}{suffix}"))
+        } else {
+            match self.regexes.iter().find(|regex| regex.is_match(domain)) {
+                Some(regex) => Cow::Borrowed(regex.as_str()),
+                None => return false,
+            }
+        };
+        self.hit(&entry);
+        true
+    }
+
+    /// Records the entry as hit, like a domain it matched in [Whitelist::contains].
+    pub fn hit(&self, entry: &str) {
+        if let Ok(mut hits) = self.hits.lock() {
+            if !hits.contains(entry) {
+                hits.insert(entry.to_owned());
+            }
+        }
+    }
+
+    /// The whitelisted suffix the domain is a subdomain of.
+    fn suffix<'a>(&self, domain: &'a str) -> Option<&'a str> {
+        domain
+            .match_indices(DOT)
+            .map(|(index, _)| &domain[index + 1..])
+            .find(|suffix| self.suffixes.contains(*suffix))
+    }
+
+    /// Returns all entries that never matched a domain, sorted.
+    pub fn unused(&self) -> Vec<String> {
+        let Ok(hits) = self.hits.lock() else {
+            return Vec::new();
+        };
+        let mut unused: Vec<_> = self
+            .domains
+            .iter()
+            .cloned()
+            .chain(
+                self.suffixes
+                    .iter()
+                    .map(|suffix| format!("{WILDCARD}{suffix}")),
+            )
+            .chain(self.regexes.iter().map(|regex| regex.as_str().to_owned()))
+            .filter(|entry| !hits.contains(entry))
+            .collect();
+        unused.sort();
+        unused
+    }
+
+    /// Merges another whitelist into this one.
+    pub fn extend(&mut self, other: Whitelist) {
+        self.domains.extend(other.domains);
+        self.suffixes.extend(other.suffixes);
+        self.regexes.extend(other.regexes);
+    }
+}
+
+/// Entries starting with [WILDCARD] are taken as suffixes, all others as exact domains.
+impl From<HashSet<String>> for Whitelist {
+    fn from(entries: HashSet<String>) -> Self {
+        let (suffixes, domains): (HashSet<_>, HashSet<_>) = entries
+            .into_iter()
+            .partition(|entry| entry.starts_with(WILDCARD));
+        Whitelist {
+            domains,
+            suffixes: suffixes
+                .into_iter()
+                .map(|suffix| suffix[WILDCARD.len()..].to_owned())
+                .collect(),
+            ..Default::default()
+        }
+    }
 }
 
+/// A file written by [crate::store::write_to_file].
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct WrittenFile {
+    /// File name relative to [crate::config::Config].path.
+    pub file: String,
+    /// Size in bytes.
+    pub size: usize,
+    pub domains: usize,
+    /// Hex encoded SHA-256 of the file content.
+    pub fingerprint: String,
+}
+
+/// Outcome of a run, see [crate::run].
+#[derive(Eq, PartialEq, Debug, Default)]
+pub struct Summary {
+    /// Number of addlists built, before they are combined.
+    pub addlists: usize,
+    /// Number of distinct entries over all addlists.
+    pub entries: usize,
+    pub sources_fetched: usize,
+    pub sources_failed: usize,
+    /// Number of remote sources skipped in offline mode.
+    pub sources_skipped: usize,
+    pub duration: Duration,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "summary addlists={} entries={} sources_fetched={} sources_failed={} sources_skipped={} duration_ms={}",
+            self.addlists,
+            self.entries,
+            self.sources_fetched,
+            self.sources_failed,
+            self.sources_skipped,
+            self.duration.as_millis()
+        )
+    }
+}
+
+/// Statistics of a run, written as `stats.json` with [crate::config::Config].stats.
+#[derive(Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct Stats {
+    /// Number of entries by written addlist.
+    pub addlists: BTreeMap<String, usize>,
+    /// Length of the fetched body by source, after it was decompressed and decoded.
+    ///
+    /// It is the size of the read list, not the bytes transferred over the network.
+    pub sources: BTreeMap<String, usize>,
+    /// Why the fetch failed by source.
+    pub failures: BTreeMap<String, String>,
+    /// Remote sources skipped in offline mode, sorted.
+    pub skipped: Vec<String>,
+    /// Duration of the run in milliseconds, from the pre hook up to writing the `stats.json`, see [Summary].duration.
+    pub duration_ms: u128,
+}
+
+#[derive(Clone)]
 pub struct AddlistConfig {
     pub name: String,
     pub config: Arc<Config>,
@@ -26,17 +267,189 @@ impl AddlistConfig {
             config,
         }
     }
+    /// Prefix of the entries, the one of the addlist, otherwise [Config].prefix or none.
     pub fn prefix(&self) -> &str {
-        match &self.config.prefix {
-            Some(prefix) => prefix,
-            None => "",
-        }
+        self.sources()
+            .and_then(|sources| sources.prefix.as_deref())
+            .or(self.config.prefix.as_deref())
+            .unwrap_or_default()
     }
 
+    /// Suffix of the entries, the one of the addlist, otherwise [Config].suffix or none.
     pub fn suffix(&self) -> &str {
-        match &self.config.suffix {
-            Some(suffix) => suffix,
-            None => "",
+        self.sources()
+            .and_then(|sources| sources.suffix.as_deref())
+            .or(self.config.suffix.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Sources of the addlist, if it is configured.
+    fn sources(&self) -> Option<&AddlistSources> {
+        self.config.addlist.get(&self.name)
+    }
+
+    /// Maximum number of entries of the addlist.
+    pub fn limit(&self) -> Option<NonZeroUsize> {
+        self.config
+            .addlist
+            .get(&self.name)
+            .and_then(|sources| sources.limit)
+    }
+
+    /// Whether the addlist is written as text file with one entry per line.
+    pub fn is_hosts(&self) -> bool {
+        self.config.output_format == OutputFormat::Hosts
+    }
+
+    /// Kind of the sources of the addlist, [SourceKind::Domains] if the addlist is not configured.
+    pub fn kind(&self) -> SourceKind {
+        self.config
+            .addlist
+            .get(&self.name)
+            .map(|sources| sources.kind)
+            .unwrap_or_default()
+    }
+
+    /// Whether the addlist is written as uBlock Origin static filters.
+    pub fn is_ublock(&self) -> bool {
+        self.config.output_format == OutputFormat::UblockStatic
+    }
+
+    /// Header lines attributing the addlist, if [Config].gravity_headers is set.
+    pub fn headers(&self) -> Vec<String> {
+        if !self.config.gravity_headers || !self.is_hosts() {
+            return Vec::new();
         }
+        let Some(sources) = self.config.addlist.get(&self.name) else {
+            return Vec::new();
+        };
+        [("Title", &sources.title), ("Homepage", &sources.homepage)]
+            .into_iter()
+            .filter_map(|(key, value)| Some(format!("{} {}: {}", COMMENT, key, value.as_ref()?)))
+            .collect()
+    }
+
+    /// Whether the CNAME chains of the domains are expanded, which never happens offline
+    /// as every DNS lookup would run into its timeout.
+    pub fn expands_cnames(&self) -> bool {
+        self.config.expand_cnames && !self.config.offline
+    }
+
+    /// Whether an entry of the addlist also blocks all subdomains of its domain.
+    pub fn matches_subdomains(&self) -> bool {
+        matches!(
+            self.config.output_format,
+            OutputFormat::UblockStatic | OutputFormat::Dnsmasq
+        )
+    }
+
+    /// Whether the domains are written with prefix and suffix.
+    pub fn is_decorated(&self) -> bool {
+        match self.config.output_format {
+            OutputFormat::Hosts => !self.prefix().is_empty() || !self.suffix().is_empty(),
+            OutputFormat::UblockStatic | OutputFormat::Dnsmasq | OutputFormat::Plain => false,
+            #[cfg(feature = "gravity")]
+            OutputFormat::GravityDb => false,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{AddlistConfig, AddlistSources, Whitelist};
+    use crate::config::Config;
+    use regex::Regex;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_whitelist_unused() -> Result<(), String> {
+        let whitelist = Whitelist {
+            domains: HashSet::from(["docs.rs".to_owned(), "t.org".to_owned()]),
+            regexes: vec![
+                Regex::new("rust-lang\\.org$").unwrap(),
+                Regex::new("^ads\\.").unwrap(),
+            ],
+            ..Default::default()
+        };
+
+        assert!(whitelist.contains("docs.rs"));
+        assert!(whitelist.contains("www.rust-lang.org"));
+        assert!(!whitelist.contains("example.com"));
+
+        assert_eq!(
+            vec!["^ads\\.".to_owned(), "t.org".to_owned()],
+            whitelist.unused()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_whitelist_suffix() -> Result<(), String> {
+        let whitelist = Whitelist::from(HashSet::from([
+            "docs.rs".to_owned(),
+            "*.example.com".to_owned(),
+        ]));
+        assert!(whitelist.contains("docs.rs"));
+        assert!(whitelist.contains("ads.example.com"));
+        assert!(whitelist.contains("a.ads.example.com"));
+        assert!(!whitelist.contains("example.com"));
+        assert!(!whitelist.contains("badexample.com"));
+        assert!(!whitelist.contains("www.docs.rs"));
+        assert!(whitelist.unused().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_addlist_config_prefix_override() -> Result<(), String> {
+        let config = Config {
+            prefix: Some("127.0.0.1 ".to_owned()),
+            suffix: Some(" # global".to_owned()),
+            addlist: HashMap::from([
+                (
+                    "Bare".to_owned(),
+                    AddlistSources {
+                        prefix: Some(String::new()),
+                        suffix: Some(String::new()),
+                        ..Default::default()
+                    },
+                ),
+                ("PiHole".to_owned(), AddlistSources::default()),
+            ]),
+            ..Default::default()
+        };
+        let config = Arc::new(config);
+
+        let bare = AddlistConfig::new("Bare", config.clone());
+        assert_eq!("", bare.prefix());
+        assert_eq!("", bare.suffix());
+        assert!(!bare.is_decorated());
+
+        let pihole = AddlistConfig::new("PiHole", config.clone());
+        assert_eq!("127.0.0.1 ", pihole.prefix());
+        assert_eq!(" # global", pihole.suffix());
+        assert!(pihole.is_decorated());
+
+        let global = Config {
+            prefix: None,
+            suffix: None,
+            ..Default::default()
+        };
+        assert_eq!("", AddlistConfig::new("Other", Arc::new(global)).prefix());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expands_cnames() -> Result<(), String> {
+        let config = |offline| Config {
+            expand_cnames: true,
+            offline,
+            ..Default::default()
+        };
+
+        assert!(AddlistConfig::new("Addlist", Arc::new(config(false))).expands_cnames());
+        assert!(!AddlistConfig::new("Addlist", Arc::new(config(true))).expands_cnames());
+        Ok(())
     }
 }
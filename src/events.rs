@@ -0,0 +1,141 @@
+use crate::data::WrittenFile;
+use log::warn;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Target that prints the events to stdout.
+const STDOUT: &str = "-";
+
+/// Whether the events of the target are printed to stdout,
+/// which then must not receive any other output.
+pub fn is_stdout(target: Option<&str>) -> bool {
+    target == Some(STDOUT)
+}
+
+/// Something that happened during a run.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    SourceFetched {
+        url: &'a str,
+        /// Length of the decompressed and decoded body.
+        bytes: usize,
+    },
+    SourceFailed {
+        url: &'a str,
+        reason: &'a str,
+    },
+    /// A remote source was not requested in offline mode.
+    SourceSkipped {
+        url: &'a str,
+    },
+    AddlistBuilt {
+        addlist: &'a str,
+        entries: usize,
+    },
+    FileWritten(&'a WrittenFile),
+}
+
+/// A single line of the event stream.
+#[derive(Serialize)]
+struct Record<'a> {
+    /// Milliseconds since the unix epoch.
+    timestamp: u128,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// Emits events as newline-delimited JSON.
+///
+/// Without a target all events are dropped.
+#[derive(Default)]
+pub struct Events {
+    writer: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Events {
+    /// Creates an emitter writing to the target, `-` writes to stdout.
+    ///
+    /// The target is appended to, so it may be a fifo.
+    ///
+    /// # Errors
+    /// - If the target could not be opened.
+    pub fn new(target: Option<&str>) -> io::Result<Events> {
+        let writer: Box<dyn Write + Send> = match target {
+            None => return Ok(Events::default()),
+            Some(STDOUT) => Box::new(io::stdout()),
+            Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+        };
+        Ok(Events {
+            writer: Some(Mutex::new(writer)),
+        })
+    }
+
+    /// Writes the event as a single line.
+    ///
+    /// Failures are reported but never abort the run.
+    pub fn emit(&self, event: Event) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let record = Record {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis())
+                .unwrap_or_default(),
+            event: &event,
+        };
+        let result = serde_json::to_string(&record)
+            .map_err(io::Error::from)
+            .and_then(|line| match writer.lock() {
+                Ok(mut writer) => writeln!(writer, "{line}").and_then(|_| writer.flush()),
+                Err(_) => Ok(()),
+            });
+        if let Err(err) = result {
+            warn!("Failed to emit event {event:?}: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{Event, Events};
+    use crate::data::WrittenFile;
+    use serde_json::Value;
+    use std::fs;
+
+    #[test]
+    fn test_emit() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events");
+        let events = Events::new(path.to_str()).map_err(|err| err.to_string())?;
+
+        events.emit(Event::SourceFetched {
+            url: "https://1.example.local",
+            bytes: 7,
+        });
+        events.emit(Event::FileWritten(&WrittenFile {
+            file: "Addlist.addlist".to_owned(),
+            size: 7,
+            domains: 1,
+            fingerprint: "aa".to_owned(),
+        }));
+
+        let lines: Vec<Value> = fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!("source_fetched", lines[0]["event"]);
+        assert_eq!(7, lines[0]["bytes"]);
+        assert!(lines[0]["timestamp"].is_u64());
+        assert_eq!("file_written", lines[1]["event"]);
+        assert_eq!("Addlist.addlist", lines[1]["file"]);
+        Ok(())
+    }
+}
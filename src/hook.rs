@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Error};
+use log::info;
+use std::collections::HashMap;
+use std::process::Command;
+
+/// Runs a configured hook command.
+///
+/// Each line of stdout in the form `KEY=VALUE` or `export KEY=VALUE` is returned as exported variable,
+/// so the hook can pass e.g. freshly minted tokens to the following fetches, see [crate::config::expand_exports].
+/// The environment of this process is left as it is.
+///
+/// # Errors
+/// - If the command could not be started.
+/// - If the command exits with a non-zero status.
+pub fn run_hook(command: &str) -> Result<HashMap<String, String>, Error> {
+    let output = shell(command)
+        .output()
+        .map_err(|err| anyhow!("Failed to start hook `{command}`: {err}"))?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Hook `{command}` failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let exports = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_export)
+        .map(|(key, value)| {
+            info!("Hook `{command}` exported {key}");
+            (key.to_owned(), value.to_owned())
+        })
+        .collect();
+    Ok(exports)
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Parses a line in the form `KEY=VALUE` or `export KEY=VALUE`.
+fn parse_export(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (key, value) = line.split_once('=')?;
+    let key = key.trim();
+    let is_valid_key =
+        !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    is_valid_key.then_some((key, value.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn test_parse_export() -> Result<(), String> {
+        assert_eq!(Some(("TOKEN", "abc")), super::parse_export("TOKEN=abc"));
+        assert_eq!(
+            Some(("TOKEN", "a=b")),
+            super::parse_export("export TOKEN=a=b")
+        );
+        assert_eq!(None, super::parse_export("Minted new token."));
+        assert_eq!(None, super::parse_export("some text = abc"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_hook_exports() -> Result<(), String> {
+        let exports = super::run_hook("echo 'minting'; echo PHDA_TEST_HOOK_TOKEN=secret")
+            .map_err(|err| err.to_string())?;
+        assert_eq!(
+            Some(&"secret".to_owned()),
+            exports.get("PHDA_TEST_HOOK_TOKEN")
+        );
+        // The variable is passed on, but not set for the whole process.
+        assert!(std::env::var_os("PHDA_TEST_HOOK_TOKEN").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_hook_fails() -> Result<(), String> {
+        let err = super::run_hook("exit 3")
+            .err()
+            .ok_or("Hook did not fail!")?;
+        assert!(err.to_string().contains("exit status: 3"), "{}", err);
+        Ok(())
+    }
+}
@@ -0,0 +1,702 @@
+#![deny(clippy::unwrap_used)]
+#![deny(clippy::expect_used)]
+#![deny(clippy::unimplemented)]
+#![deny(unsafe_code)]
+#![warn(clippy::filter_map_next)]
+#![warn(clippy::flat_map_option)]
+#![warn(clippy::implicit_clone)]
+
+pub mod aggregate;
+pub mod config;
+pub mod data;
+pub mod events;
+pub mod hook;
+pub mod selftest;
+pub mod store;
+pub mod thread;
+
+use aggregate::fetch::Fetcher;
+use aggregate::lists::{addlist, addlists_by_source, combine, dedupe, whitelist};
+use aggregate::pihole;
+use anyhow::{anyhow, Error};
+use config::{config_hash, expand_exports, Config};
+use data::{Addlist, AddlistConfig, SourceKind, Stats, Summary, Whitelist};
+use events::{Event, Events};
+use hook::run_hook;
+use log::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{mpsc, Arc};
+use std::time::Instant;
+use store::{write_file_list, write_index, write_stats, write_to_file};
+use thread::{StopSignal, ThreadPool};
+
+/// Creates all addlists as in the givn Config definded.
+///
+/// If configured the pre hook runs before anything is fetched, its exported variables are expanded in the config,
+/// and an index and a list of all written files are created afterwards.
+/// The index is tagged with the hash of the config.
+/// With `events` the progress is emitted as newline-delimited JSON.
+/// The addlists are built like [aggregate_all], each is written as soon as it is built.
+/// Combined and deduplicated addlists are written once all addlists are built.
+/// With `dry_run` nothing is written, the number of entries of each addlist is printed instead.
+/// With `stats` the statistics of the run are written last, see [Stats].
+///
+/// Returns a [Summary] of the run, which also counts addlists that could not be written.
+///
+/// # Errors
+/// - If the pre hook fails.
+/// - If a variable referenced by the config is neither exported by the pre hook nor set.
+/// - If the config is invalid.
+/// - If the events target could not be opened.
+/// - If the Pi-hole allowlist could not be read.
+/// - If the ThreadPool could not be created.
+/// - If the run was interrupted before all addlists were written, see [thread::request_stop].
+/// - If the combined addlist could not be written.
+/// - If the `gravity.db` could not be read to list it.
+/// - If the list of written files could not be written.
+/// - If the index could not be written.
+/// - If the statistics could not be written.
+pub fn run(config: Config) -> Result<Summary, Error> {
+    let start = Instant::now();
+    // The variables exported by the hook may complete the config, so it is validated afterwards.
+    let config = match &config.pre_hook {
+        Some(command) => {
+            let exports = run_hook(command)?;
+            expand_exports(config, &exports)?
+        }
+        None => config,
+    };
+    config.validate()?;
+    let events = Arc::new(Events::new(config.events.as_deref())?);
+    let config_hash = config_hash(&config)?;
+    let config = Arc::new(config);
+
+    let mut files = Vec::new();
+    let (mut summary, mut stats) = aggregate(&config, &events, |addlist_config, data| {
+        if config.dry_run {
+            // Events written to stdout must not be mixed with other output.
+            if events::is_stdout(config.events.as_deref()) {
+                eprintln!("{} -> {} entries", data.name, data.list.len());
+            } else {
+                println!("{} -> {} entries", data.name, data.list.len());
+            }
+            return Ok(());
+        }
+        match write_to_file(addlist_config, data) {
+            Ok(written) => {
+                for file in &written {
+                    events.emit(Event::FileWritten(file));
+                }
+                files.extend(written);
+            }
+            Err(err) if config.combine_into.is_some() => return Err(err.into()),
+            Err(err) => error!("{:?}", err),
+        }
+        Ok(())
+    })?;
+    if !config.dry_run {
+        #[cfg(feature = "gravity")]
+        let files = store::merge_gravity(&config.path, files)?;
+        if let Some(target) = &config.written_files {
+            write_file_list(&config.path, target, &files)?;
+        }
+        if let Some(format) = config.index {
+            write_index(&config.path, format, &config_hash, files)?;
+        }
+    }
+    summary.duration = start.elapsed();
+    if config.stats && !config.dry_run {
+        stats.duration_ms = summary.duration.as_millis();
+        write_stats(&config.path, &stats)?;
+    }
+    Ok(summary)
+}
+
+/// Builds all addlists as in the given Config defined without writing anything.
+///
+/// With `split_by_source` an addlist is built per source host of each addlist.
+/// With `combine_into` all addlists are combined into a single addlist instead.
+/// With `dedupe_across_lists` each entry is only kept in the addlist with the lowest name.
+/// Both only apply to addlists of domains, addlists of other kinds are returned as they are.
+/// The caches of the config are not used, so no file is written.
+/// The addlists are returned sorted by name.
+///
+/// # Errors
+/// - If the config is invalid, apart from the output path, which is not used.
+/// - If the Pi-hole allowlist could not be read.
+/// - If the ThreadPool could not be created.
+/// - If the run was interrupted before all addlists were built, see [thread::request_stop].
+pub fn aggregate_all(config: Config) -> Result<Vec<Addlist>, Error> {
+    config.validate_without_output()?;
+    let config = Config {
+        validation_cache: None,
+        http_cache: None,
+        breaker_cache: None,
+        ..config
+    };
+    let mut addlists = Vec::new();
+    aggregate(&Arc::new(config), &Arc::default(), |_, data| {
+        addlists.push(data);
+        Ok(())
+    })?;
+    addlists.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(addlists)
+}
+
+/// Builds all addlists on the ThreadPool and passes each one to `output` as soon as it is built,
+/// together with the config of the addlist it was built from.
+///
+/// Addlists of domains that are combined or deduplicated depend on each other,
+/// so they are passed once all addlists are built.
+/// Once a stop was requested no further addlist is passed, as it is likely incomplete.
+/// Returns the metrics of the build, the [Stats] lack the duration, which is up to the caller.
+///
+/// # Errors
+/// - If the Pi-hole allowlist could not be read.
+/// - If the ThreadPool could not be created.
+/// - If `output` fails.
+/// - If the run was interrupted before all addlists were built.
+fn aggregate<F>(
+    config: &Arc<Config>,
+    events: &Arc<Events>,
+    mut output: F,
+) -> Result<(Summary, Stats), Error>
+where
+    F: FnMut(AddlistConfig, Addlist) -> Result<(), Error>,
+{
+    // Only stops requested during this run apply to it.
+    let stop = StopSignal::new();
+    let fetcher = Arc::new(
+        Fetcher::new(config)
+            .with_events(events.clone())
+            .with_stop(stop),
+    );
+    let mut whitelist = Whitelist::from(whitelist(&config.whitelist, &fetcher).unwrap_or_default());
+    if let Some(path) = &config.pihole_allowlist {
+        whitelist.extend(pihole::allowlist(path)?);
+    }
+    let whitelist = Arc::new(whitelist);
+    let progress = Arc::new(|completed, total| info!("{completed}/{total} addlists done"));
+    let pool = ThreadPool::new_with_progress(config.threads, config.use_all_cores, progress)?
+        .with_stop(stop);
+    let (sender, receiver) = mpsc::channel();
+
+    for (addlist_name, _) in config.addlist.iter() {
+        let addlist_config = AddlistConfig::new(addlist_name, config.clone());
+        let whitelist = whitelist.clone();
+        let fetcher = fetcher.clone();
+        let sender = sender.clone();
+        let events = events.clone();
+
+        pool.execute(move || {
+            // Jobs queued before the stop are dropped as well.
+            if stop.is_stopped() {
+                return;
+            }
+            let addlists = match addlist_config.config.split_by_source {
+                Some(split) => addlists_by_source(&addlist_config, split, whitelist, &fetcher),
+                None => addlist(&addlist_config, whitelist, &fetcher).map(|data| vec![data]),
+            };
+            for data in addlists.unwrap_or_default() {
+                events.emit(Event::AddlistBuilt {
+                    addlist: &data.name,
+                    entries: data.list.len(),
+                });
+                sender
+                    .send((addlist_config.clone(), data))
+                    .unwrap_or_default();
+            }
+        })
+    }
+    drop(sender);
+
+    // Only domains are combined or deduplicated, regexes and IP addresses are passed as they are.
+    let cross_list = config.combine_into.is_some() || config.dedupe_across_lists;
+    let mut summary = Summary::default();
+    let mut stats = Stats::default();
+    // Only hashes of the entries are kept to count them, so the addlists aren't held in memory.
+    let hasher = RandomState::new();
+    let mut entries = HashSet::new();
+    let mut collected = Vec::new();
+    // Receives the addlists while the remaining ones are still built.
+    for (addlist_config, data) in receiver {
+        // The addlist is likely incomplete, so its previous files are kept.
+        if stop.is_stopped() {
+            break;
+        }
+        summary.addlists += 1;
+        entries.extend(data.list.iter().map(|entry| hasher.hash_one(entry)));
+        if cross_list && addlist_config.kind() == SourceKind::Domains {
+            collected.push((addlist_config, data));
+        } else {
+            stats.addlists.insert(data.name.clone(), data.list.len());
+            output(addlist_config, data)?;
+        }
+    }
+    // Waits until all workers are finished.
+    drop(pool);
+    if stop.is_stopped() {
+        return Err(anyhow!(
+            "Interrupted, the remaining addlists were not written."
+        ));
+    }
+
+    for url in fetcher.tripped() {
+        warn!("Source {url} is tripped.");
+    }
+    if config.report_failed_sources {
+        for (url, failure) in fetcher.failures() {
+            if failure.is_dead() {
+                warn!("Source {url} failed with {failure}, it is likely dead and can be removed.");
+            } else {
+                warn!("Source {url} failed with {failure}.");
+            }
+        }
+    }
+    if config.report_unused_whitelist {
+        for entry in whitelist.unused() {
+            warn!("Whitelist entry {entry} did not match any domain.");
+        }
+    }
+
+    summary.entries = entries.len();
+    (summary.sources_fetched, summary.sources_failed) = fetcher.outcomes();
+    stats.skipped = fetcher.skipped();
+    summary.sources_skipped = stats.skipped.len();
+    for (url, outcome) in fetcher.source_outcomes() {
+        match outcome {
+            Ok(bytes) => {
+                stats.sources.insert(url, bytes);
+            }
+            Err(reason) => {
+                stats.failures.insert(url, reason.to_owned());
+            }
+        }
+    }
+
+    let collected = if let Some(name) = &config.combine_into {
+        let addlist_config = AddlistConfig::new(name, config.clone());
+        // Separators are comments, which only text files support.
+        let separators = config.combined_separators && addlist_config.is_hosts();
+        let addlists = collected.into_iter().map(|(_, data)| data).collect();
+        let combined = combine(name, addlists, separators);
+        events.emit(Event::AddlistBuilt {
+            addlist: &combined.name,
+            entries: combined.list.len(),
+        });
+        vec![(addlist_config, combined)]
+    } else {
+        let (addlist_configs, addlists): (HashMap<_, _>, Vec<_>) = collected
+            .into_iter()
+            .map(|(addlist_config, data)| ((data.name.clone(), addlist_config), data))
+            .unzip();
+        dedupe(addlists)
+            .into_iter()
+            .filter_map(|data| Some((addlist_configs.get(&data.name)?.clone(), data)))
+            .collect()
+    };
+    for (addlist_config, data) in collected {
+        stats.addlists.insert(data.name.clone(), data.list.len());
+        output(addlist_config, data)?;
+    }
+    Ok((summary, stats))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::config::{Config, IndexFormat};
+    use crate::data::{AddlistSources, SourceKind, Stats};
+    use itertools::Itertools;
+    use mockito::mock;
+    use std::collections::BTreeMap;
+    use std::collections::HashMap;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn test_run_dry_run() -> Result<(), String> {
+        let mocked = mock("GET", "/dry-run")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            dry_run: true,
+            whitelist: None,
+            index: Some(IndexFormat::Json),
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([mockito::server_url() + "/dry-run"]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        super::run(config).map_err(|err| err.to_string())?;
+
+        mocked.assert();
+        assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_dedupe_across_lists() -> Result<(), String> {
+        let first = mock("GET", "/dedupe-first")
+            .with_status(200)
+            .with_body("docs.rs\ncrates.io")
+            .create();
+        let second = mock("GET", "/dedupe-second")
+            .with_status(200)
+            .with_body("docs.rs\nrust-lang.org")
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_owned();
+        let sources = |url: &str| AddlistSources {
+            addlist: HashSet::from([mockito::server_url() + url]),
+            ..Default::default()
+        };
+        let config = Config {
+            path: path.clone(),
+            dedupe_across_lists: true,
+            prefix: None,
+            suffix: None,
+            size: None,
+            whitelist: None,
+            addlist: HashMap::from([
+                ("A".to_owned(), sources("/dedupe-first")),
+                ("B".to_owned(), sources("/dedupe-second")),
+            ]),
+            ..Default::default()
+        };
+
+        super::run(config).map_err(|err| err.to_string())?;
+
+        first.assert();
+        second.assert();
+        let read = |name: &str| fs::read_to_string(format!("{path}/{name}")).unwrap();
+        assert!(read("A.addlist").contains("docs.rs"));
+        assert!(!read("B.addlist").contains("docs.rs"));
+        assert!(read("B.addlist").contains("rust-lang.org"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_summary() -> Result<(), String> {
+        let first = mock("GET", "/summary-first")
+            .with_status(200)
+            .with_body("docs.rs\ncrates.io")
+            .create();
+        let second = mock("GET", "/summary-second")
+            .with_status(200)
+            .with_body("docs.rs\nrust-lang.org")
+            .create();
+        let failed = mock("GET", "/summary-failed").with_status(404).create();
+        let dir = tempfile::tempdir().unwrap();
+        let sources = |urls: &[&str]| AddlistSources {
+            addlist: urls.iter().map(|url| mockito::server_url() + url).collect(),
+            ..Default::default()
+        };
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            prefix: None,
+            suffix: None,
+            whitelist: None,
+            www_duplication: Some(false),
+            addlist: HashMap::from([
+                (
+                    "A".to_owned(),
+                    sources(&["/summary-first", "/summary-failed"]),
+                ),
+                ("B".to_owned(), sources(&["/summary-second"])),
+            ]),
+            ..Default::default()
+        };
+
+        let summary = super::run(config).map_err(|err| err.to_string())?;
+
+        first.assert();
+        second.assert();
+        failed.assert();
+        assert_eq!(2, summary.addlists);
+        assert_eq!(3, summary.entries);
+        assert_eq!(2, summary.sources_fetched);
+        assert_eq!(1, summary.sources_failed);
+        assert!(summary.to_string().starts_with(
+            "summary addlists=2 entries=3 sources_fetched=2 sources_failed=1 sources_skipped=0 duration_ms="
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_combine_into() -> Result<(), String> {
+        let ads = mock("GET", "/combine-ads")
+            .with_status(200)
+            .with_body("docs.rs\ncrates.io")
+            .create();
+        let tracking = mock("GET", "/combine-tracking")
+            .with_status(200)
+            .with_body("docs.rs\nrust-lang.org")
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let sources = |url: &str| AddlistSources {
+            addlist: HashSet::from([mockito::server_url() + url]),
+            ..Default::default()
+        };
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            combine_into: Some("Combined".to_owned()),
+            prefix: None,
+            suffix: None,
+            size: NonZeroUsize::new(2),
+            www_duplication: Some(false),
+            whitelist: None,
+            addlist: HashMap::from([
+                ("Ads".to_owned(), sources("/combine-ads")),
+                ("Tracking".to_owned(), sources("/combine-tracking")),
+            ]),
+            ..Default::default()
+        };
+
+        super::run(config).map_err(|err| err.to_string())?;
+
+        ads.assert();
+        tracking.assert();
+        let files = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .sorted()
+            .collect_vec();
+        assert_eq!(vec!["0-Combined.addlist", "1-Combined.addlist"], files);
+        let read = |file: &str| fs::read_to_string(dir.path().join(file)).unwrap();
+        assert_eq!("crates.io\r\ndocs.rs", read("0-Combined.addlist"));
+        assert_eq!("rust-lang.org", read("1-Combined.addlist"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_combine_into_other_kinds() -> Result<(), String> {
+        let domains = mock("GET", "/combine-kinds-domains")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+        let regexes = mock("GET", "/combine-kinds-regexes")
+            .with_status(200)
+            .with_body("^ad[0-9]+\\.")
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let sources = |url: &str, kind| AddlistSources {
+            addlist: HashSet::from([mockito::server_url() + url]),
+            kind,
+            ..Default::default()
+        };
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            combine_into: Some("Combined".to_owned()),
+            prefix: None,
+            suffix: None,
+            size: None,
+            www_duplication: Some(false),
+            whitelist: None,
+            addlist: HashMap::from([
+                (
+                    "Ads".to_owned(),
+                    sources("/combine-kinds-domains", SourceKind::Domains),
+                ),
+                (
+                    "Regexes".to_owned(),
+                    sources("/combine-kinds-regexes", SourceKind::Regex),
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        super::run(config).map_err(|err| err.to_string())?;
+
+        domains.assert();
+        regexes.assert();
+        let files = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .sorted()
+            .collect_vec();
+        assert_eq!(vec!["Combined.addlist", "Regexes.regex"], files);
+        let read = |file: &str| fs::read_to_string(dir.path().join(file)).unwrap();
+        assert_eq!("docs.rs", read("Combined.addlist"));
+        assert_eq!("^ad[0-9]+\\.", read("Regexes.regex"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_stats() -> Result<(), String> {
+        let ads = mock("GET", "/stats-ads")
+            .with_status(200)
+            .with_body("docs.rs\ncrates.io")
+            .create();
+        let trackers = mock("GET", "/stats-trackers")
+            .with_status(200)
+            .with_body("rust-lang.org")
+            .create();
+        let failed = mock("GET", "/stats-failed").with_status(404).create();
+        let dir = tempfile::tempdir().unwrap();
+        let url = |path: &str| mockito::server_url() + path;
+        let sources = |paths: &[&str]| AddlistSources {
+            addlist: paths.iter().map(|path| url(path)).collect(),
+            ..Default::default()
+        };
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            prefix: None,
+            suffix: None,
+            whitelist: None,
+            www_duplication: Some(false),
+            stats: true,
+            addlist: HashMap::from([
+                ("Ads".to_owned(), sources(&["/stats-ads", "/stats-failed"])),
+                ("Trackers".to_owned(), sources(&["/stats-trackers"])),
+            ]),
+            ..Default::default()
+        };
+
+        super::run(config).map_err(|err| err.to_string())?;
+
+        ads.assert();
+        trackers.assert();
+        failed.assert();
+        let raw = fs::read_to_string(dir.path().join("stats.json")).unwrap();
+        let stats: Stats = serde_json::from_str(&raw).map_err(|err| err.to_string())?;
+        assert_eq!(
+            BTreeMap::from([("Ads".to_owned(), 2), ("Trackers".to_owned(), 1)]),
+            stats.addlists
+        );
+        assert_eq!(
+            BTreeMap::from([(url("/stats-ads"), 17), (url("/stats-trackers"), 13)]),
+            stats.sources
+        );
+        assert_eq!(
+            vec![url("/stats-failed")],
+            stats.failures.into_keys().collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_audit() -> Result<(), String> {
+        let source = mock("GET", "/audit")
+            .with_status(200)
+            .with_body("docs.rs\ncrates.io\nrust-lang.org\nt.org")
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            prefix: None,
+            suffix: None,
+            size: None,
+            whitelist: None,
+            www_duplication: Some(false),
+            audit: true,
+            index: Some(IndexFormat::Json),
+            addlist: HashMap::from([(
+                "Audited".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([mockito::server_url() + "/audit"]),
+                    whitelist: Some(HashSet::from(["crates.io".to_owned(), "t.org".to_owned()])),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        super::run(config).map_err(|err| err.to_string())?;
+
+        source.assert();
+        assert_eq!(
+            "docs.rs\r\nrust-lang.org",
+            fs::read_to_string(dir.path().join("Audited.addlist")).unwrap()
+        );
+        assert_eq!(
+            "crates.io\r\nt.org",
+            fs::read_to_string(dir.path().join("Audited.removed")).unwrap()
+        );
+        let index = fs::read_to_string(dir.path().join("index.json")).unwrap();
+        assert!(index.contains("\"Audited.removed\""), "{index}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_fetches_shared_source_once() -> Result<(), String> {
+        let shared = mock("GET", "/shared-source")
+            .with_status(200)
+            .with_body("docs.rs")
+            .expect(1)
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let sources = || AddlistSources {
+            addlist: HashSet::from([mockito::server_url() + "/shared-source"]),
+            ..Default::default()
+        };
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            prefix: None,
+            suffix: None,
+            size: None,
+            whitelist: None,
+            www_duplication: Some(false),
+            addlist: HashMap::from([
+                ("Ads".to_owned(), sources()),
+                ("Trackers".to_owned(), sources()),
+            ]),
+            ..Default::default()
+        };
+
+        super::run(config).map_err(|err| err.to_string())?;
+
+        shared.assert();
+        for name in ["Ads", "Trackers"] {
+            let written = fs::read_to_string(dir.path().join(format!("{name}.addlist"))).unwrap();
+            assert!(written.contains("docs.rs"), "{name}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_pre_hook_exports() -> Result<(), String> {
+        let source = mock("GET", "/hook-source")
+            .with_status(200)
+            .with_body("docs.rs")
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            prefix: None,
+            suffix: None,
+            size: None,
+            whitelist: None,
+            www_duplication: Some(false),
+            pre_hook: Some(format!("echo PHDA_TEST_HOOK_URL={}", mockito::server_url())),
+            addlist: HashMap::from([(
+                "Hooked".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from(["${PHDA_TEST_HOOK_URL}/hook-source".to_owned()]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        super::run(config).map_err(|err| err.to_string())?;
+
+        source.assert();
+        assert_eq!(
+            "docs.rs",
+            fs::read_to_string(dir.path().join("Hooked.addlist")).unwrap()
+        );
+        Ok(())
+    }
+}
@@ -6,42 +6,163 @@
 #![warn(clippy::flat_map_option)]
 #![warn(clippy::implicit_clone)]
 
-mod aggregate;
-mod config;
-mod data;
-mod store;
-mod thread;
-
-use aggregate::lists::{addlist, whitelist};
-use anyhow::Error;
-use config::parse_config;
-use data::AddlistConfig;
-use std::sync::Arc;
-use store::write_to_file;
-use thread::ThreadPool;
+mod cli;
+
+use anyhow::{anyhow, Error};
+use cli::{Args, Command};
+use env_logger::Env;
+use log::{warn, LevelFilter};
+use phda::aggregate::validation::check;
+use phda::config::{
+    explain, format_config, parse_config, parse_remote_config, Config, CONFIG_PATH,
+};
+use phda::events::is_stdout;
+use phda::run;
+use phda::selftest::self_test;
+use phda::store::stale_files;
+use phda::thread::{request_stop, stop_requested};
+use std::env;
+use std::process::{self, ExitCode};
+use std::time::Duration;
 
 /// Creates all addlists as in the givn Config definded.
 ///
 /// # Errors
+/// - If the arguments are invalid.
 /// - If the Config is invalid.
-fn main() -> Result<(), Error> {
-    let config = parse_config()?;
-    let whitelist = Arc::new(whitelist(&config.whitelist).unwrap_or_default());
-    let pool = ThreadPool::new(config.threads)?;
-    
-    let config = Arc::new(config);
-    for (addlist_name, _) in config.addlist.iter() {
-        let addlist_config = AddlistConfig::new(addlist_name, config.clone());
-        let whitelist = whitelist.clone();
-
-        pool.execute(move || {
-            if let Some(data) = addlist(&addlist_config, whitelist) {
-                if let Some(err) = write_to_file(addlist_config, data).err() {
-                    eprint!("{:?}", err);
-                }
+fn main() -> Result<ExitCode, Error> {
+    init_logger();
+    ctrlc::set_handler(|| {
+        if stop_requested() {
+            process::exit(INTERRUPTED);
+        }
+        warn!("Interrupted, finishing the running jobs. Press Ctrl-C again to exit immediately.");
+        request_stop();
+    })?;
+    let args = cli::parse(env::args().skip(1))?;
+    match args.command {
+        Command::Run => {
+            let config = config(&args)?;
+            // Events written to stdout must not be mixed with other output.
+            if is_stdout(config.events.as_deref()) {
+                eprintln!("{}", run(config)?);
+            } else {
+                println!("{}", run(config)?);
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::SelfTest => Ok(print_self_test(config(&args)?)),
+        Command::CheckStale => check_stale(&config(&args)?),
+        Command::FormatConfig => {
+            let path = args.config.as_deref().unwrap_or(CONFIG_PATH);
+            if is_url(path) {
+                return Err(anyhow!("`--format-config` only rewrites the local config"));
             }
-        })
+            let path = format_config(path)?;
+            println!("Rewrote {path}, the original is backed up to {path}.bak.");
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::ExplainConfig => {
+            println!("{}", explain(&config(&args)?)?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::ValidateDomain(domain) => Ok(validate_domain(&domain, args.verbose)),
+    }
+}
+
+fn config(args: &Args) -> Result<Config, Error> {
+    let mut config = match args.config.as_deref().unwrap_or(CONFIG_PATH) {
+        url if is_url(url) => parse_remote_config(url, args.config_cache.as_deref())?,
+        path => parse_config(path)?,
+    };
+    config.use_all_cores |= args.all_cores;
+    config.dry_run |= args.dry_run;
+    if args.events {
+        config.events.get_or_insert_with(|| "-".to_owned());
+    }
+    if let (Some(level), None) = (config.log_level, env::var_os(RUST_LOG)) {
+        log::set_max_level(level);
+    }
+    Ok(config)
+}
+
+/// Exit code of a process terminated by `SIGINT`.
+const INTERRUPTED: i32 = 130;
+
+/// Environment variable the log filter is read from, see [env_logger].
+const RUST_LOG: &str = "RUST_LOG";
+/// Log filter without `RUST_LOG`, which keeps the debug output of dependencies like reqwest off.
+const DEFAULT_FILTER: &str = "warn,phda=trace";
+
+/// Logs to stderr, filtered by `RUST_LOG` or otherwise by the `log_level` of the config.
+///
+/// Without `RUST_LOG` everything of this program passes the logger, so the threshold can be set once the config is parsed.
+/// Until then messages up to `info` are logged. Dependencies only log warnings and errors, even at `debug`.
+fn init_logger() {
+    env_logger::Builder::from_env(Env::default().default_filter_or(DEFAULT_FILTER)).init();
+    if env::var_os(RUST_LOG).is_none() {
+        log::set_max_level(LevelFilter::Info);
     }
+}
 
-    Ok(())
+/// Whether the config is fetched from a URL instead of read from a local file.
+fn is_url(config: &str) -> bool {
+    config.starts_with("http://") || config.starts_with("https://")
+}
+
+/// Prints the result of every check and fails if any check failed.
+fn print_self_test(config: Config) -> ExitCode {
+    let checks = self_test(config);
+    for check in &checks {
+        match &check.error {
+            None => println!("PASS {}", check.name),
+            Some(err) => println!("FAIL {}: {err}", check.name),
+        }
+    }
+    let passed = checks.iter().filter(|check| check.passed()).count();
+    println!("{passed} of {} checks passed.", checks.len());
+    if passed == checks.len() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Prints all stale files and fails if there is any.
+///
+/// # Errors
+/// - If `max_output_age_secs` is not configured.
+/// - If the written files could not be read.
+fn check_stale(config: &Config) -> Result<ExitCode, Error> {
+    let max_age = config
+        .max_output_age_secs
+        .ok_or_else(|| anyhow!("`max_output_age_secs` is not configured"))?;
+    let stale = stale_files(config, Duration::from_secs(max_age.get()))?;
+    for file in &stale {
+        match file.age {
+            Some(age) => println!("{} is {} seconds old.", file.file, age.as_secs()),
+            None => println!("{} is missing.", file.file),
+        }
+    }
+    if stale.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Prints the normalized domain or fails if the domain would be rejected.
+fn validate_domain(domain: &str, verbose: bool) -> ExitCode {
+    match check(&domain.to_lowercase()) {
+        Ok(domain) => {
+            println!("{domain}");
+            ExitCode::SUCCESS
+        }
+        Err(reason) => {
+            if verbose {
+                eprintln!("{domain} is rejected: {reason}.");
+            }
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,175 @@
+use crate::aggregate::fetch::{local_path, Fetcher};
+use crate::aggregate::lists::{addlist, whitelist};
+use crate::config::Config;
+use crate::data::{AddlistConfig, Whitelist};
+use itertools::Itertools;
+use reqwest::Url;
+use std::fs;
+use std::sync::Arc;
+
+/// File written to check that the output directory is writable.
+const PROBE_FILE: &str = ".phda-self-test";
+
+/// Result of a single pre-flight check.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Check {
+    pub name: String,
+    /// Reason why the check failed.
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, result: Result<(), String>) -> Check {
+        Check {
+            name: name.into(),
+            error: result.err(),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Checks that a deployment works with the given Config.
+///
+/// Validates the config, checks that the output directory is writable,
+/// probes every source and makes sure at least one addlist is not empty.
+/// Nothing is written except a probe file that is removed again.
+pub fn self_test(config: Config) -> Vec<Check> {
+    let fetcher = Fetcher::new(&config);
+    let mut checks = vec![
+        Check::new("config", validate(&config)),
+        Check::new("writable output", writable(&config.path)),
+    ];
+
+    checks.extend(
+        sources(&config)
+            .into_iter()
+            .map(|url| Check::new(format!("reachable {url}"), fetcher.probe(&url))),
+    );
+
+    let global_whitelist = Arc::new(Whitelist::from(
+        whitelist(&config.whitelist, &fetcher).unwrap_or_default(),
+    ));
+    let config = Arc::new(config);
+    let non_empty = config.addlist.keys().sorted().any(|name| {
+        let addlist_config = AddlistConfig::new(name, config.clone());
+        addlist(&addlist_config, global_whitelist.clone(), &fetcher)
+            .is_some_and(|addlist| !addlist.list.is_empty())
+    });
+    checks.push(Check::new(
+        "non-empty addlist",
+        if non_empty {
+            Ok(())
+        } else {
+            Err("Every addlist is empty".to_owned())
+        },
+    ));
+    checks
+}
+
+fn validate(config: &Config) -> Result<(), String> {
+    config.validate().map_err(|err| err.to_string())
+}
+
+fn writable(path: &str) -> Result<(), String> {
+    let file = format!("{path}/{PROBE_FILE}");
+    fs::write(&file, []).map_err(|err| format!("{path}: {err}"))?;
+    fs::remove_file(&file).map_err(|err| format!("{path}: {err}"))
+}
+
+/// All sources of the addlists and whitelists, without inline whitelist domains.
+fn sources(config: &Config) -> Vec<String> {
+    let addlists = config
+        .addlist
+        .values()
+        .flat_map(|sources| sources.addlist.iter());
+    let whitelists = config
+        .addlist
+        .values()
+        .filter_map(|sources| sources.whitelist.as_ref())
+        .chain(config.whitelist.as_ref())
+        .flatten()
+        .filter(|source| Url::parse(source).is_ok() || local_path(source).is_some());
+    addlists
+        .chain(whitelists)
+        .unique()
+        .sorted()
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::config::Config;
+    use crate::data::AddlistSources;
+    use mockito::mock;
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+
+    fn config(path: &str, source: String) -> Config {
+        Config {
+            path: path.to_owned(),
+            whitelist: None,
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    addlist: HashSet::from([source]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_self_test() -> Result<(), String> {
+        let mock = mock("GET", "/self-test")
+            .with_status(200)
+            .with_body("docs.rs")
+            .expect(2)
+            .create();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        let checks = super::self_test(config(path, mockito::server_url() + "/self-test"));
+
+        mock.assert();
+        assert_eq!(4, checks.len());
+        assert!(checks.iter().all(|check| check.passed()), "{checks:?}");
+        assert_eq!(0, fs::read_dir(path).unwrap().count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_test_failures() -> Result<(), String> {
+        let mock = mock("GET", "/self-test-down")
+            .with_status(404)
+            .expect(2)
+            .create();
+
+        let checks = super::self_test(config(
+            "./does-not-exist",
+            mockito::server_url() + "/self-test-down",
+        ));
+
+        mock.assert();
+        let failed = checks
+            .iter()
+            .filter(|check| !check.passed())
+            .map(|check| check.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "config",
+                "writable output",
+                &format!("reachable {}/self-test-down", mockito::server_url()),
+                "non-empty addlist"
+            ],
+            failed
+        );
+        Ok(())
+    }
+}
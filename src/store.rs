@@ -1,31 +1,1333 @@
-use crate::data::{Addlist, AddlistConfig};
+#[cfg(feature = "gravity")]
+mod gravity;
+
+use crate::aggregate::lists::{source_addlist_name, COMMENT};
+use crate::config::{Config, IndexFormat, OutputFormat};
+use crate::data::{Addlist, AddlistConfig, SourceKind, Stats, WrittenFile};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use itertools::Itertools;
+use log::info;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::num::NonZeroUsize;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
 use std::{fs, io::Write};
+use unicode_normalization::UnicodeNormalization;
+
+const INDEX: &str = "index";
+const STATS: &str = "stats.json";
+/// Target of the list of written files that prints it to stdout.
+pub const STDOUT: &str = "-";
+const DEFAULT_EXTENSION: &str = "addlist";
+const DNSMASQ_EXTENSION: &str = "conf";
+const REGEX_EXTENSION: &str = "regex";
+const IP_EXTENSION: &str = "ip";
+const GZIP_EXTENSION: &str = "gz";
+const LAST_MODIFIED_EXTENSION: &str = "last-modified.json";
+const REMOVED_EXTENSION: &str = "removed";
+
+/// Number of output files currently open across all threads.
+static OPEN_FILES: Mutex<usize> = Mutex::new(0);
+/// Notified whenever an output file is closed.
+static FILE_CLOSED: Condvar = Condvar::new();
+
+/// Permit to keep an output file open, released when dropped.
+struct OpenFile;
+
+impl OpenFile {
+    /// Waits until fewer than `limit` output files are open.
+    fn acquire(limit: Option<NonZeroUsize>) -> OpenFile {
+        let mut open = OPEN_FILES.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(limit) = limit {
+            open = FILE_CLOSED
+                .wait_while(open, |open| *open >= limit.get())
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *open += 1;
+        OpenFile
+    }
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        let mut open = OPEN_FILES.lock().unwrap_or_else(PoisonError::into_inner);
+        *open -= 1;
+        FILE_CLOSED.notify_one();
+    }
+}
 
 /// Writes addlist to (multiple) file(s).
 ///
 /// Based on [lib::config::Config].size attribute the addlist is split into multiple files or written all at one file.
+/// With [lib::config::Config].balanced_chunks the files are of about the same size, see [chunks].
+/// With [lib::config::Config].subdir_per_addlist the files are written into `{path}/{name}/`,
+/// chunks are then named `{num}.addlist` and a single file `{name}.addlist`.
+/// Returns the written files.
+/// The files are named with [lib::config::Config].output_extension
+/// and gzip compressed with [lib::config::Config].compress_output.
+///
+/// At most [lib::config::Config].max_open_files files are open at the same time, across all threads.
+///
+/// All files are written to temporary files first and only moved into place once every file was written,
+/// so a failure never leaves a partially updated set of chunks behind.
+/// Files whose content is identical to the existing file are not written at all, which keeps their mtime,
+/// as Pi-hole may reload on every change. With [lib::config::Config].header this never applies, as the header is dated.
+///
+/// With [lib::config::Config].last_modified the dates of the domains are written next to the files.
+/// With [lib::config::Config].audit the domains removed by a whitelist are listed in `{name}.removed` next to the files,
+/// which is returned as written file as well.
+/// With [lib::config::Config].header every file starts with a generated header, which includes the number of a chunk.
+///
+/// For [OutputFormat::GravityDb] the addlist is stored in the `gravity.db` instead.
 ///
 /// # Errors
 /// - If file could not be created or manipulated.
-pub fn write_to_file(config: AddlistConfig, addlist: Addlist) -> std::io::Result<()> {
-    match config.config.size {
-        Some(size) => addlist
-            .list
-            .chunks(size.get())
-            .map(|data| data.join("\r\n"))
+pub fn write_to_file(config: AddlistConfig, addlist: Addlist) -> std::io::Result<Vec<WrittenFile>> {
+    match config.config.output_format {
+        OutputFormat::Hosts
+        | OutputFormat::UblockStatic
+        | OutputFormat::Dnsmasq
+        | OutputFormat::Plain => {}
+        // Regexes and IPs are written to files, as they can't be stored as adlist domains.
+        #[cfg(feature = "gravity")]
+        OutputFormat::GravityDb if config.kind() != SourceKind::Domains => {}
+        #[cfg(feature = "gravity")]
+        OutputFormat::GravityDb => {
+            return gravity::write_to_db(&config.config.path, &addlist)
+                .map(|file| vec![file])
+                .map_err(std::io::Error::other);
+        }
+    }
+    let subdir = config.config.subdir_per_addlist;
+    let files = match config.config.size {
+        Some(size) => chunks(&addlist.list, size, config.config.balanced_chunks)
+            .into_iter()
             .enumerate()
-            .try_for_each(|(num, data)| {
-                let mut file = fs::File::create(format!(
-                    "{}/{}-{}.addlist",
-                    config.config.path, num, addlist.name
-                ))?;
-                file.write_all(data.as_bytes()).map(|_| ())
-            })?,
+            .map(|(num, data)| {
+                let file = file_name(&config.config, &addlist.name, config.kind(), Some(num));
+                (file, Some(num), data)
+            })
+            .collect(),
         None => {
-            let mut file =
-                fs::File::create(format!("{}/{}.addlist", config.config.path, addlist.name))?;
-            file.write_all(addlist.list.join("\r\n").as_bytes())?
+            let file = file_name(&config.config, &addlist.name, config.kind(), None);
+            vec![(file, None, addlist.list.as_slice())]
+        }
+    };
+
+    let path = &config.config.path;
+    if subdir {
+        fs::create_dir_all(format!("{}/{}", path, addlist.name))?;
+    }
+    let mut written = Vec::new();
+    let mut changed = Vec::new();
+    let mut unchanged = Vec::new();
+    for (file, chunk, data) in files {
+        match write(&config, file, chunk, data) {
+            Ok((file, true)) => {
+                changed.push(file.file.clone());
+                written.push(file);
+            }
+            Ok((file, false)) => {
+                info!("{} unchanged.", file.file);
+                unchanged.push(file.file.clone());
+                written.push(file);
+            }
+            Err(err) => {
+                for file in &changed {
+                    let _ = fs::remove_file(temp(path, file));
+                }
+                return Err(err);
+            }
         }
     }
+    for file in &changed {
+        fs::rename(temp(path, file), format!("{}/{}", path, file))?;
+    }
+    // The unchanged files keep their modification time, the marker tells they are still up to date.
+    for file in &unchanged {
+        fs::write(checked(path, file), "")?;
+    }
+    if config.config.last_modified {
+        write_last_modified(path, &addlist.name, &addlist.last_modified)?;
+    }
+    if config.config.audit {
+        let file = format!("{}.{}", addlist.name, REMOVED_EXTENSION);
+        let data = addlist.removed.join("\r\n").into_bytes();
+        write_atomic(path, &file, &data)?;
+        written.push(WrittenFile {
+            file,
+            size: data.len(),
+            domains: addlist.removed.len(),
+            fingerprint: fingerprint(&data),
+        });
+    }
+    Ok(written)
+}
+
+/// Splits the domains into chunks of at most `size` domains.
+///
+/// Unless balanced, all chunks but the last one are filled up to `size`.
+/// Balanced, the same number of chunks is used, but their sizes differ by at most one domain.
+fn chunks(domains: &[String], size: NonZeroUsize, balanced: bool) -> Vec<&[String]> {
+    if !balanced || domains.is_empty() {
+        return domains.chunks(size.get()).collect();
+    }
+    let count = domains.len().div_ceil(size.get());
+    let (base, larger) = (domains.len() / count, domains.len() % count);
+    let mut rest = domains;
+    (0..count)
+        .map(|num| {
+            let (chunk, tail) = rest.split_at(base + usize::from(num < larger));
+            rest = tail;
+            chunk
+        })
+        .collect()
+}
+
+/// Comment lines describing how a file of the addlist was generated, see [lib::config::Config].header.
+///
+/// uBlock Origin filters are commented with `!`, all other formats with `#`.
+fn generated_header(
+    config: &AddlistConfig,
+    chunk: Option<usize>,
+    domains: usize,
+    now: SystemTime,
+) -> Vec<String> {
+    let comment = if config.is_ublock() { '!' } else { COMMENT };
+    let mut header = vec![
+        format!("{} Generated by PiHole-domain-aggregator", comment),
+        format!("{} Addlist: {}", comment, config.name),
+    ];
+    if let Some(chunk) = chunk {
+        header.push(format!("{} Chunk: {}", comment, chunk));
+    }
+    header.push(format!("{} Domains: {}", comment, domains));
+    header.push(format!("{} Date: {}", comment, rfc3339(now)));
+    header
+}
+
+/// Formats the time as RFC 3339 timestamp in UTC, like `2024-02-29T13:37:00Z`.
+fn rfc3339(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // Converts the days since the epoch into a civil date, see http://howardhinnant.github.io/date_algorithms.html.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+/// Writes the last modified dates of the domains as JSON object into `{name}.last-modified.json`.
+fn write_last_modified(
+    path: &str,
+    name: &str,
+    last_modified: &BTreeMap<String, String>,
+) -> std::io::Result<()> {
+    let file = format!("{}.{}", name, LAST_MODIFIED_EXTENSION);
+    write_atomic(path, &file, &serde_json::to_vec_pretty(last_modified)?)
+}
+
+/// Number of temporary files created by [write_atomic], which makes their names unique.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes the file into `path` by moving a completely written temporary file into place,
+/// so readers either see the old or the new file.
+///
+/// Each write uses its own temporary file, so concurrent writes of the same file never mix.
+pub fn write_atomic(path: &str, file: &str, data: &[u8]) -> std::io::Result<()> {
+    let temp = format!(
+        "{}.{}-{}",
+        temp(path, file),
+        process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    );
+    let result = fs::File::create(&temp).and_then(|mut f| {
+        f.write_all(data)?;
+        f.sync_all()
+    });
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+    fs::rename(temp, format!("{}/{}", path, file))
+}
+
+/// Extension of the written files, followed by `.gz` if they are compressed.
+///
+/// Regex addlists are always written as `.regex` and IP addlists as `.ip`. Without [lib::config::Config].output_extension
+/// dnsmasq configs are written as `.conf`, everything else as `.addlist`.
+fn extension(config: &Config, kind: SourceKind) -> String {
+    let default = match config.output_format {
+        OutputFormat::Dnsmasq => DNSMASQ_EXTENSION,
+        _ => DEFAULT_EXTENSION,
+    };
+    let extension = match kind {
+        SourceKind::Regex => REGEX_EXTENSION,
+        SourceKind::Ip => IP_EXTENSION,
+        SourceKind::Domains => config
+            .output_extension
+            .as_deref()
+            .map_or(default, |extension| extension.trim_start_matches('.')),
+    };
+    if config.compress_output {
+        format!("{}.{}", extension, GZIP_EXTENSION)
+    } else {
+        extension.to_owned()
+    }
+}
+
+/// Path of a written file of the addlist relative to [lib::config::Config].path.
+///
+/// A single file is named `{addlist}.{extension}` and chunks `{num}-{addlist}.{extension}`,
+/// with [lib::config::Config].subdir_per_addlist `{addlist}/{addlist}.{extension}` and `{addlist}/{num}.{extension}`.
+fn file_name(config: &Config, addlist: &str, kind: SourceKind, chunk: Option<usize>) -> String {
+    let extension = extension(config, kind);
+    match (chunk, config.subdir_per_addlist) {
+        (Some(num), true) => format!("{}/{}.{}", addlist, num, extension),
+        (Some(num), false) => format!("{}-{}.{}", num, addlist, extension),
+        (None, true) => format!("{}/{}.{}", addlist, addlist, extension),
+        (None, false) => format!("{}.{}", addlist, extension),
+    }
+}
+
+/// Lists the `gravity.db` of [OutputFormat::GravityDb] once, though every addlist stored in it reports it.
+///
+/// # Errors
+/// - If the database could not be read.
+#[cfg(feature = "gravity")]
+pub fn merge_gravity(path: &str, files: Vec<WrittenFile>) -> std::io::Result<Vec<WrittenFile>> {
+    gravity::merge(path, files)
+}
+
+/// Path of the temporary file the given file is written to before it is moved into place.
+///
+/// The temporary file is hidden in the same directory as the file.
+fn temp(path: &str, file: &str) -> String {
+    match file.rsplit_once('/') {
+        Some((dir, file)) => format!("{}/{}/.{}.tmp", path, dir, file),
+        None => format!("{}/.{}.tmp", path, file),
+    }
+}
+
+/// Path of the marker that is touched whenever the given file was found unchanged.
+///
+/// Like the temporary file, the marker is hidden in the same directory as the file.
+fn checked(path: &str, file: &str) -> String {
+    match file.rsplit_once('/') {
+        Some((dir, file)) => format!("{}/{}/.{}.checked", path, dir, file),
+        None => format!("{}/.{}.checked", path, file),
+    }
+}
+
+/// Writes the file to its temporary file, see [temp].
+///
+/// Returns whether the content changed. An unchanged file is not written.
+fn write(
+    config: &AddlistConfig,
+    file: String,
+    chunk: Option<usize>,
+    domains: &[String],
+) -> std::io::Result<(WrittenFile, bool)> {
+    // Regexes and IP addresses are read without comments, so only domains get the header.
+    let header = if config.config.header && config.kind() == SourceKind::Domains {
+        generated_header(config, chunk, domains.len(), SystemTime::now())
+    } else {
+        Vec::new()
+    };
+    let data = header
+        .iter()
+        .chain(&config.headers())
+        .chain(domains)
+        .join("\r\n");
+    let data = if config.config.output_ascii_only {
+        ascii(&data)
+    } else {
+        data
+    };
+    let data = if config.config.compress_output {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data.as_bytes())?;
+        encoder.finish()?
+    } else {
+        data.into_bytes()
+    };
+    let written = WrittenFile {
+        file,
+        size: data.len(),
+        domains: domains.len(),
+        fingerprint: fingerprint(&data),
+    };
+    let existing = fs::read(format!("{}/{}", config.config.path, written.file));
+    if existing.is_ok_and(|existing| fingerprint(&existing) == written.fingerprint) {
+        return Ok((written, false));
+    }
+    let temp = temp(&config.config.path, &written.file);
+    let permit = OpenFile::acquire(config.config.max_open_files);
+    // Synced so a crash after the rename never leaves a truncated file in place.
+    let result = fs::File::create(&temp).and_then(|mut f| {
+        f.write_all(&data)?;
+        f.sync_all()
+    });
+    drop(permit);
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp);
+        return Err(err);
+    }
+    Ok((written, true))
+}
+
+/// Drops the lines whose entry is not ASCII and transliterates comments to ASCII.
+///
+/// Entries are never transliterated, as `münchen.de` would become the different domain `munchen.de`.
+/// Characters of comments are replaced by their ASCII base character, those without are dropped.
+fn ascii(data: &str) -> String {
+    data.split("\r\n")
+        .filter_map(|line| {
+            let (entry, comment) = line.split_at(line.find(COMMENT).unwrap_or(line.len()));
+            let comment: String = comment.nfd().filter(char::is_ascii).collect();
+            entry.is_ascii().then(|| format!("{entry}{comment}"))
+        })
+        .join("\r\n")
+}
+
+/// Hex encoded SHA-256 of the given data.
+pub fn fingerprint(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Index of all written files.
+#[derive(Serialize)]
+struct Index<'a> {
+    /// Hash of the config the files were created with.
+    config_hash: &'a str,
+    files: Vec<WrittenFile>,
+}
+
+/// Writes an index of all written files into [lib::config::Config].path.
+///
+/// The files are listed sorted by name so the index is stable between runs.
+/// Like the addlist files, the index is replaced atomically.
+/// The index is tagged with the hash of the config the files were created with.
+///
+/// # Errors
+/// - If file could not be created or manipulated.
+pub fn write_index(
+    path: &str,
+    format: IndexFormat,
+    config_hash: &str,
+    mut files: Vec<WrittenFile>,
+) -> Result<(), anyhow::Error> {
+    files.sort_by(|a, b| a.file.cmp(&b.file));
+    let (extension, data) = match format {
+        IndexFormat::Json => (
+            "json",
+            serde_json::to_string_pretty(&Index { config_hash, files })?,
+        ),
+        IndexFormat::Txt => (
+            "txt",
+            [format!("# config {config_hash}")]
+                .into_iter()
+                .chain(files.iter().map(|file| {
+                    format!(
+                        "{}\t{}\t{}\t{}",
+                        file.file, file.size, file.domains, file.fingerprint
+                    )
+                }))
+                .collect::<Vec<_>>()
+                .join("\r\n"),
+        ),
+    };
+    write_atomic(path, &format!("{}.{}", INDEX, extension), data.as_bytes())?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Writes the statistics of the run into `stats.json` in [lib::config::Config].path.
+///
+/// Like the addlist files, the statistics are replaced atomically.
+///
+/// # Errors
+/// - If file could not be created or manipulated.
+pub fn write_stats(path: &str, stats: &Stats) -> std::io::Result<()> {
+    write_atomic(path, STATS, &serde_json::to_vec_pretty(stats)?)
+}
+
+/// Lists the paths of all written files sorted, one per line.
+///
+/// The list is written to `target` or printed to stdout if `target` is `-`.
+///
+/// # Errors
+/// - If file could not be created or manipulated.
+pub fn write_file_list(path: &str, target: &str, files: &[WrittenFile]) -> std::io::Result<()> {
+    let list: String = files
+        .iter()
+        .map(|file| format!("{}/{}\n", path, file.file))
+        .sorted()
+        .collect();
+    if target == STDOUT {
+        print!("{list}");
+        Ok(())
+    } else {
+        fs::write(target, list)
+    }
+}
+
+/// An expected output file that is missing or older than allowed.
+#[derive(Debug, Eq, PartialEq)]
+pub struct StaleFile {
+    pub file: String,
+    /// Age of the file, `None` if no file of the addlist exists.
+    pub age: Option<Duration>,
+}
+
+/// Finds the written addlist files in [lib::config::Config].path that are older than `max_age`.
+///
+/// Every addlist, or the combined addlist and the addlists that are no domains,
+/// is expected to have at least one file, chunks of an addlist are checked individually.
+/// With [lib::config::Config].split_by_source every source host of a domain addlist is expected instead.
+/// The files are named by [file_name] like they are written.
+/// A file that was last found unchanged counts as written at that time, see [checked].
+///
+/// # Errors
+/// - If the files could not be read.
+pub fn stale_files(config: &Config, max_age: Duration) -> std::io::Result<Vec<StaleFile>> {
+    let kind = |name: &str| {
+        config
+            .addlist
+            .get(name)
+            .map(|sources| sources.kind)
+            .unwrap_or_default()
+    };
+    // Addlists that are no domains are written on their own even if the others are combined.
+    let names: Vec<(String, SourceKind)> = match &config.combine_into {
+        Some(name) => std::iter::once((name.clone(), SourceKind::Domains))
+            .chain(
+                config
+                    .addlist
+                    .iter()
+                    .filter(|(_, sources)| sources.kind != SourceKind::Domains)
+                    .sorted_by_key(|(name, _)| *name)
+                    .map(|(name, sources)| (name.clone(), sources.kind)),
+            )
+            .collect(),
+        None => config
+            .addlist
+            .iter()
+            .sorted_by_key(|(name, _)| *name)
+            .flat_map(|(name, sources)| match config.split_by_source {
+                Some(_) if sources.kind == SourceKind::Domains => sources
+                    .addlist
+                    .iter()
+                    .map(|url| source_addlist_name(name, url))
+                    .unique()
+                    .sorted()
+                    .collect_vec(),
+                _ => vec![name.clone()],
+            })
+            .map(|name| {
+                let kind = kind(&name);
+                (name, kind)
+            })
+            .collect(),
+    };
+
+    let exists = |file: &String| fs::metadata(format!("{}/{}", config.path, file)).is_ok();
+    let now = SystemTime::now();
+    let mut stale = Vec::new();
+    for (name, kind) in names {
+        let single = file_name(config, &name, kind, None);
+        let files = std::iter::once(single.clone())
+            .filter(exists)
+            .chain(
+                (0..)
+                    .map(|num| file_name(config, &name, kind, Some(num)))
+                    .take_while(exists),
+            )
+            .collect_vec();
+        if files.is_empty() {
+            stale.push(StaleFile {
+                file: single,
+                age: None,
+            });
+        }
+        for file in files {
+            let mut modified = fs::metadata(format!("{}/{}", config.path, file))?.modified()?;
+            match fs::metadata(checked(&config.path, &file)) {
+                Ok(marker) => modified = modified.max(marker.modified()?),
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            let age = now.duration_since(modified).unwrap_or_default();
+            if age > max_age {
+                stale.push(StaleFile {
+                    file,
+                    age: Some(age),
+                });
+            }
+        }
+    }
+    Ok(stale)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::StaleFile;
+    use crate::aggregate::lists::mutate;
+    use crate::config::{Config, IndexFormat, OutputFormat, SplitBySource};
+    use crate::data::{Addlist, AddlistConfig, AddlistSources, SourceKind, WrittenFile};
+    use flate2::read::GzDecoder;
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::fs;
+    use std::io::Read;
+    use std::num::NonZeroUsize;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    fn addlist_config(path: &str, size: Option<NonZeroUsize>) -> AddlistConfig {
+        let config = Config {
+            path: path.to_owned(),
+            size,
+            ..Default::default()
+        };
+        AddlistConfig::new("Addlist", Arc::new(config))
+    }
+
+    #[test]
+    fn test_write_to_file_chunks() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["a.com".to_owned(), "b.com".to_owned(), "c.com".to_owned()],
+            ..Default::default()
+        };
+
+        let have = super::write_to_file(addlist_config(path, NonZeroUsize::new(2)), addlist)
+            .map_err(|err| err.to_string())?;
+
+        assert_eq!(2, have.len());
+        assert_eq!("0-Addlist.addlist", have[0].file);
+        assert_eq!(2, have[0].domains);
+        assert_eq!(12, have[0].size);
+        assert_eq!(super::fingerprint(b"a.com\r\nb.com"), have[0].fingerprint);
+        assert_eq!(
+            "c.com",
+            fs::read_to_string(dir.path().join("1-Addlist.addlist")).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_max_open_files() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_owned();
+        let config = Arc::new(Config {
+            path,
+            size: NonZeroUsize::new(1),
+            max_open_files: NonZeroUsize::new(2),
+            ..Default::default()
+        });
+
+        let handles = (0..4)
+            .map(|num| {
+                let config = AddlistConfig::new(&format!("Addlist{num}"), config.clone());
+                let addlist = Addlist {
+                    name: config.name.clone(),
+                    list: (0..500).map(|domain| format!("{domain}.com")).collect(),
+                    ..Default::default()
+                };
+                std::thread::spawn(move || super::write_to_file(config, addlist))
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            let have = handle.join().unwrap().map_err(|err| err.to_string())?;
+            assert_eq!(500, have.len());
+        }
+        assert_eq!(2000, fs::read_dir(dir.path()).unwrap().count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_chunks_failure() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        fs::write(dir.path().join("0-Addlist.addlist"), "old").unwrap();
+        // A directory in place of the temporary file makes writing the second chunk fail.
+        fs::create_dir(dir.path().join(".1-Addlist.addlist.tmp")).unwrap();
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["a.com".to_owned(), "b.com".to_owned(), "c.com".to_owned()],
+            ..Default::default()
+        };
+
+        let have = super::write_to_file(addlist_config(path, NonZeroUsize::new(1)), addlist);
+
+        assert!(have.is_err());
+        assert_eq!(
+            "old",
+            fs::read_to_string(dir.path().join("0-Addlist.addlist")).unwrap()
+        );
+        assert!(!dir.path().join("1-Addlist.addlist").exists());
+        assert!(!dir.path().join(".0-Addlist.addlist.tmp").exists());
+        assert!(!dir.path().join(".2-Addlist.addlist.tmp").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_index() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let files = vec![
+            WrittenFile {
+                file: "b.addlist".to_owned(),
+                size: 5,
+                domains: 1,
+                fingerprint: "bb".to_owned(),
+            },
+            WrittenFile {
+                file: "a.addlist".to_owned(),
+                size: 12,
+                domains: 2,
+                fingerprint: "aa".to_owned(),
+            },
+        ];
+
+        super::write_index(path, IndexFormat::Json, "cc", files.clone()).unwrap();
+        super::write_index(path, IndexFormat::Txt, "cc", files.clone()).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("index.json")).unwrap())
+                .unwrap();
+        assert_eq!("cc", json["config_hash"]);
+        let json: Vec<WrittenFile> = serde_json::from_value(json["files"].clone()).unwrap();
+        assert_eq!(vec![files[1].clone(), files[0].clone()], json);
+        assert_eq!(
+            "# config cc\r\na.addlist\t12\t2\taa\r\nb.addlist\t5\t1\tbb",
+            fs::read_to_string(dir.path().join("index.txt")).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_file_list() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("written");
+        let files = ["1-Addlist.addlist", "0-Addlist.addlist"].map(|file| WrittenFile {
+            file: file.to_owned(),
+            size: 0,
+            domains: 0,
+            fingerprint: String::new(),
+        });
+
+        super::write_file_list("./addlists", target.to_str().unwrap(), &files)
+            .map_err(|err| err.to_string())?;
+
+        assert_eq!(
+            "./addlists/0-Addlist.addlist\n./addlists/1-Addlist.addlist\n",
+            fs::read_to_string(target).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_ascii_only() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            size: None,
+            output_ascii_only: true,
+            ..Default::default()
+        };
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["xn--mller-brombel-rmb4fg.de # müller-büromöbel.de ✓".to_owned()],
+            ..Default::default()
+        };
+
+        super::write_to_file(AddlistConfig::new("Addlist", Arc::new(config)), addlist)
+            .map_err(|err| err.to_string())?;
+
+        let have = fs::read_to_string(dir.path().join("Addlist.addlist")).unwrap();
+        assert!(have.is_ascii());
+        assert_eq!("xn--mller-brombel-rmb4fg.de # muller-buromobel.de ", have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_ascii_only_unicode_domain() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            size: None,
+            output_ascii_only: true,
+            ..Default::default()
+        };
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["xn--mnchen-3ya.de".to_owned(), "münchen.de".to_owned()],
+            ..Default::default()
+        };
+
+        super::write_to_file(AddlistConfig::new("Addlist", Arc::new(config)), addlist)
+            .map_err(|err| err.to_string())?;
+
+        let have = fs::read_to_string(dir.path().join("Addlist.addlist")).unwrap();
+        assert_eq!("xn--mnchen-3ya.de", have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_compressed() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            size: None,
+            compress_output: true,
+            ..Default::default()
+        };
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["a.com".to_owned(), "b.com".to_owned()],
+            ..Default::default()
+        };
+
+        let have = super::write_to_file(AddlistConfig::new("Addlist", Arc::new(config)), addlist)
+            .map_err(|err| err.to_string())?;
+
+        assert_eq!("Addlist.addlist.gz", have[0].file);
+        let mut data = String::new();
+        GzDecoder::new(fs::File::open(dir.path().join("Addlist.addlist.gz")).unwrap())
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!("a.com\r\nb.com", data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_extension() -> Result<(), String> {
+        let cases = [
+            (None, false, "Addlist.addlist"),
+            (Some("txt"), false, "Addlist.txt"),
+            (Some(".list"), false, "Addlist.list"),
+            (None, true, "Addlist.addlist.gz"),
+            (Some("txt"), true, "Addlist.txt.gz"),
+        ];
+        for (extension, compress_output, want) in cases {
+            let dir = tempfile::tempdir().unwrap();
+            let config = Config {
+                path: dir.path().to_str().unwrap().to_owned(),
+                size: None,
+                output_extension: extension.map(str::to_owned),
+                compress_output,
+                ..Default::default()
+            };
+            let addlist = Addlist {
+                name: "Addlist".to_owned(),
+                list: vec!["a.com".to_owned()],
+                ..Default::default()
+            };
+
+            let have =
+                super::write_to_file(AddlistConfig::new("Addlist", Arc::new(config)), addlist)
+                    .map_err(|err| err.to_string())?;
+
+            assert_eq!(want, have[0].file);
+            assert!(dir.path().join(want).exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_files() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut config = Config {
+            path: path.to_owned(),
+            ..Default::default()
+        };
+        config.addlist = ["Fresh", "Old", "Missing"]
+            .into_iter()
+            .map(|name| (name.to_owned(), AddlistSources::default()))
+            .collect();
+        fs::write(dir.path().join("Fresh.addlist"), "a.com").unwrap();
+        fs::write(dir.path().join("0-Old.addlist"), "a.com").unwrap();
+        let old = fs::File::options()
+            .write(true)
+            .open(dir.path().join("0-Old.addlist"))
+            .unwrap();
+        old.set_modified(SystemTime::now() - Duration::from_secs(7200))
+            .unwrap();
+
+        let have = super::stale_files(&config, Duration::from_secs(3600))
+            .map_err(|err| err.to_string())?;
+
+        assert_eq!(2, have.len());
+        assert_eq!(
+            StaleFile {
+                file: "Missing.addlist".to_owned(),
+                age: None
+            },
+            have[0]
+        );
+        assert_eq!("0-Old.addlist", have[1].file);
+        assert!(have[1].age.unwrap() >= Duration::from_secs(7200));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_files_split_by_source() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut config = Config {
+            path: path.to_owned(),
+            split_by_source: Some(SplitBySource::PreWhitelist),
+            subdir_per_addlist: true,
+            ..Default::default()
+        };
+        let sources = AddlistSources {
+            addlist: HashSet::from([
+                "https://a.com/list".to_owned(),
+                "https://a.com/other".to_owned(),
+                "https://b.com/list".to_owned(),
+            ]),
+            ..Default::default()
+        };
+        config.addlist = HashMap::from([("Ads".to_owned(), sources)]);
+        fs::create_dir(dir.path().join("Ads-a.com")).unwrap();
+        fs::write(dir.path().join("Ads-a.com/0.addlist"), "a.com").unwrap();
+
+        let have = super::stale_files(&config, Duration::from_secs(3600))
+            .map_err(|err| err.to_string())?;
+
+        let want = vec![StaleFile {
+            file: "Ads-b.com/Ads-b.com.addlist".to_owned(),
+            age: None,
+        }];
+        assert_eq!(want, have);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_last_modified() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let config = Config {
+            path: path.to_owned(),
+            last_modified: true,
+            ..Default::default()
+        };
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["docs.rs".to_owned(), "www.docs.rs".to_owned()],
+            last_modified: BTreeMap::from([(
+                "docs.rs".to_owned(),
+                "Wed, 21 Oct 2015 07:28:00 GMT".to_owned(),
+            )]),
+            ..Default::default()
+        };
+
+        super::write_to_file(AddlistConfig::new("Addlist", Arc::new(config)), addlist)
+            .map_err(|err| err.to_string())?;
+
+        let written: BTreeMap<String, String> = serde_json::from_str(
+            &fs::read_to_string(dir.path().join("Addlist.last-modified.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            Some("Wed, 21 Oct 2015 07:28:00 GMT"),
+            written.get("docs.rs").map(String::as_str)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_subdir_per_addlist() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let config = Arc::new(Config {
+            path: path.to_owned(),
+            size: NonZeroUsize::new(2),
+            subdir_per_addlist: true,
+            ..Default::default()
+        });
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["a.com".to_owned(), "b.com".to_owned(), "c.com".to_owned()],
+            ..Default::default()
+        };
+
+        let have = super::write_to_file(AddlistConfig::new("Addlist", config.clone()), addlist)
+            .map_err(|err| err.to_string())?;
+
+        assert_eq!(
+            vec!["Addlist/0.addlist", "Addlist/1.addlist"],
+            have.iter()
+                .map(|file| file.file.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "c.com",
+            fs::read_to_string(dir.path().join("Addlist").join("1.addlist")).unwrap()
+        );
+        assert_eq!(1, fs::read_dir(path).unwrap().count());
+        assert_eq!(2, fs::read_dir(dir.path().join("Addlist")).unwrap().count());
+
+        let config = Config {
+            size: None,
+            addlist: HashMap::from([
+                ("Addlist".to_owned(), AddlistSources::default()),
+                ("Missing".to_owned(), AddlistSources::default()),
+            ]),
+            ..(*config).clone()
+        };
+        let addlist = Addlist {
+            name: "Single".to_owned(),
+            list: vec!["a.com".to_owned()],
+            ..Default::default()
+        };
+        let have = super::write_to_file(
+            AddlistConfig::new("Single", Arc::new(config.clone())),
+            addlist,
+        )
+        .map_err(|err| err.to_string())?;
+        assert_eq!("Single/Single.addlist", have[0].file);
+
+        let stale = super::stale_files(&config, Duration::ZERO).map_err(|err| err.to_string())?;
+        assert_eq!(
+            vec![
+                "Addlist/0.addlist",
+                "Addlist/1.addlist",
+                "Missing/Missing.addlist"
+            ],
+            stale
+                .iter()
+                .map(|file| file.file.as_str())
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_gravity_headers() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let config = Config {
+            path: path.to_owned(),
+            gravity_headers: true,
+            size: None,
+            addlist: HashMap::from([(
+                "Addlist".to_owned(),
+                AddlistSources {
+                    title: Some("Ads".to_owned()),
+                    homepage: Some("https://example.local".to_owned()),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["a.com".to_owned()],
+            ..Default::default()
+        };
+
+        let have = super::write_to_file(AddlistConfig::new("Addlist", Arc::new(config)), addlist)
+            .map_err(|err| err.to_string())?;
+
+        assert_eq!(1, have[0].domains);
+        assert_eq!(
+            "# Title: Ads\r\n# Homepage: https://example.local\r\na.com",
+            fs::read_to_string(dir.path().join("Addlist.addlist")).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_dnsmasq() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let config = Arc::new(Config {
+            path: path.to_owned(),
+            size: None,
+            output_format: OutputFormat::Dnsmasq,
+            ..Default::default()
+        });
+        let addlist_config = AddlistConfig::new("Addlist", config);
+        let domains = ["www.docs.rs", "t.org"].map(str::to_owned);
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: mutate(&addlist_config, HashSet::from(domains)),
+            ..Default::default()
+        };
+
+        let have = super::write_to_file(addlist_config, addlist).map_err(|err| err.to_string())?;
+
+        assert_eq!("Addlist.conf", have[0].file);
+        assert_eq!(
+            "address=/t.org/0.0.0.0\r\naddress=/www.docs.rs/0.0.0.0",
+            fs::read_to_string(dir.path().join("Addlist.conf")).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_no_temp_files() -> Result<(), String> {
+        for size in [None, NonZeroUsize::new(1)] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().to_str().unwrap();
+            fs::write(dir.path().join("Addlist.addlist"), "old").unwrap();
+            let addlist = Addlist {
+                name: "Addlist".to_owned(),
+                list: vec!["a.com".to_owned(), "b.com".to_owned()],
+                ..Default::default()
+            };
+
+            let have = super::write_to_file(addlist_config(path, size), addlist)
+                .map_err(|err| err.to_string())?;
+            super::write_index(path, IndexFormat::Txt, "hash", have)
+                .map_err(|err| err.to_string())?;
+
+            let files = fs::read_dir(path)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            assert!(
+                files.iter().all(|file| !file.ends_with(".tmp")),
+                "{files:?}"
+            );
+            let (file, content) = match size {
+                None => ("Addlist.addlist", "a.com\r\nb.com"),
+                Some(_) => ("1-Addlist.addlist", "b.com"),
+            };
+            assert_eq!(content, fs::read_to_string(dir.path().join(file)).unwrap());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_regex() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            size: None,
+            prefix: None,
+            suffix: None,
+            output_extension: Some("txt".to_owned()),
+            header: true,
+            addlist: HashMap::from([(
+                "Regex".to_owned(),
+                AddlistSources {
+                    kind: SourceKind::Regex,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let addlist = Addlist {
+            name: "Regex".to_owned(),
+            list: vec!["^ad[0-9]+\\.".to_owned()],
+            ..Default::default()
+        };
+
+        let have = super::write_to_file(AddlistConfig::new("Regex", Arc::new(config)), addlist)
+            .map_err(|err| err.to_string())?;
+
+        assert_eq!("Regex.regex", have[0].file);
+        assert_eq!(
+            "^ad[0-9]+\\.",
+            fs::read_to_string(dir.path().join("Regex.regex")).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_header() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            path: dir.path().to_str().unwrap().to_owned(),
+            size: None,
+            prefix: None,
+            suffix: None,
+            header: true,
+            ..Default::default()
+        };
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["a.com".to_owned(), "b.com".to_owned()],
+            ..Default::default()
+        };
+
+        super::write_to_file(AddlistConfig::new("Addlist", Arc::new(config)), addlist)
+            .map_err(|err| err.to_string())?;
+
+        let have = fs::read_to_string(dir.path().join("Addlist.addlist")).unwrap();
+        let lines = have.split("\r\n").collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "# Generated by PiHole-domain-aggregator",
+                "# Addlist: Addlist",
+                "# Domains: 2",
+            ],
+            lines[..3]
+        );
+        assert!(lines[3].starts_with("# Date: 20"), "{have}");
+        assert!(lines[3].ends_with('Z'), "{have}");
+        assert_eq!(vec!["a.com", "b.com"], lines[4..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rfc3339() -> Result<(), String> {
+        let cases = [
+            (0, "1970-01-01T00:00:00Z"),
+            (951_782_400, "2000-02-29T00:00:00Z"),
+            (1_709_213_820, "2024-02-29T13:37:00Z"),
+        ];
+        for (secs, want) in cases {
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(want, super::rfc3339(time));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_unchanged() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let file = dir.path().join("Addlist.addlist");
+        let addlist = |list: &[&str]| Addlist {
+            name: "Addlist".to_owned(),
+            list: list.iter().map(|domain| domain.to_string()).collect(),
+            ..Default::default()
+        };
+        super::write_to_file(addlist_config(path, None), addlist(&["a.com", "b.com"]))
+            .map_err(|err| err.to_string())?;
+        let old = SystemTime::now() - Duration::from_secs(7200);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let have = super::write_to_file(addlist_config(path, None), addlist(&["a.com", "b.com"]))
+            .map_err(|err| err.to_string())?;
+        assert_eq!(1, have.len());
+        assert_eq!(old, fs::metadata(&file).unwrap().modified().unwrap());
+        assert!(!dir.path().join(".Addlist.addlist.tmp").exists());
+
+        super::write_to_file(addlist_config(path, None), addlist(&["a.com"]))
+            .map_err(|err| err.to_string())?;
+        assert_ne!(old, fs::metadata(&file).unwrap().modified().unwrap());
+        assert_eq!("a.com", fs::read_to_string(&file).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stale_files_unchanged() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let addlist = || Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["a.com".to_owned()],
+            ..Default::default()
+        };
+        let config = Config {
+            path: path.to_owned(),
+            size: None,
+            addlist: HashMap::from([("Addlist".to_owned(), AddlistSources::default())]),
+            ..Default::default()
+        };
+        let config = AddlistConfig::new("Addlist", Arc::new(config));
+        super::write_to_file(config.clone(), addlist()).map_err(|err| err.to_string())?;
+        fs::File::options()
+            .write(true)
+            .open(dir.path().join("Addlist.addlist"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(7200))
+            .unwrap();
+        let max_age = Duration::from_secs(3600);
+        let stale = super::stale_files(&config.config, max_age).map_err(|err| err.to_string())?;
+        assert_eq!(1, stale.len());
+
+        super::write_to_file(config.clone(), addlist()).map_err(|err| err.to_string())?;
+
+        let stale = super::stale_files(&config.config, max_age).map_err(|err| err.to_string())?;
+        assert_eq!(Vec::<StaleFile>::new(), stale);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_to_file_balanced_chunks() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let config = Config {
+            path: path.to_owned(),
+            prefix: None,
+            suffix: None,
+            size: NonZeroUsize::new(1000),
+            balanced_chunks: true,
+            ..Default::default()
+        };
+        let addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: (0..2003).map(|num| format!("d{num}.docs.rs")).collect(),
+            ..Default::default()
+        };
+
+        let have = super::write_to_file(AddlistConfig::new("Addlist", Arc::new(config)), addlist)
+            .map_err(|err| err.to_string())?;
+
+        let files = have
+            .iter()
+            .map(|file| file.file.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "0-Addlist.addlist",
+                "1-Addlist.addlist",
+                "2-Addlist.addlist"
+            ],
+            files
+        );
+        let domains = have.iter().map(|file| file.domains).collect::<Vec<_>>();
+        assert_eq!(vec![668, 668, 667], domains);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunks_balanced() -> Result<(), String> {
+        let domains = (0..7).map(|num| num.to_string()).collect::<Vec<_>>();
+        let size = NonZeroUsize::new(3).unwrap();
+        let lens =
+            |chunks: Vec<&[String]>| chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>();
+        assert_eq!(vec![3, 3, 1], lens(super::chunks(&domains, size, false)));
+        assert_eq!(vec![3, 2, 2], lens(super::chunks(&domains, size, true)));
+        assert!(super::chunks(&[], size, true).is_empty());
+        assert_eq!(domains, super::chunks(&domains, size, true).concat());
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use crate::data::{Addlist, WrittenFile};
+use crate::store::fingerprint;
+use rusqlite::{params, Connection};
+use std::fs;
+use std::time::Duration;
+
+pub const GRAVITY_DB: &str = "gravity.db";
+const ADDRESS: &str = "phda://";
+
+/// Subset of the Pi-hole gravity schema that is needed to store addlists.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS adlist (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    address TEXT UNIQUE NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    date_added INTEGER NOT NULL DEFAULT (cast(strftime('%s', 'now') as int)),
+    date_modified INTEGER NOT NULL DEFAULT (cast(strftime('%s', 'now') as int)),
+    comment TEXT,
+    number INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS gravity (
+    domain TEXT NOT NULL,
+    adlist_id INTEGER NOT NULL REFERENCES adlist (id)
+);
+";
+
+/// Stores the addlist in the `gravity.db` located in path.
+///
+/// Each addlist is an adlist with the address `phda://{name}`. Its previous domains are replaced.
+///
+/// # Errors
+/// - If the database could not be opened or manipulated.
+pub fn write_to_db(path: &str, addlist: &Addlist) -> rusqlite::Result<WrittenFile> {
+    let mut connection = Connection::open(format!("{}/{}", path, GRAVITY_DB))?;
+    // Multiple workers write into the same database.
+    connection.busy_timeout(Duration::from_secs(60))?;
+    connection.execute_batch(SCHEMA)?;
+
+    let transaction = connection.transaction()?;
+    let address = format!("{}{}", ADDRESS, addlist.name);
+    transaction.execute(
+        "INSERT INTO adlist (address, comment) VALUES (?1, ?2) ON CONFLICT(address) DO NOTHING",
+        params![address, "Managed by PiHole-domain-aggregator"],
+    )?;
+    let id: i64 = transaction.query_row(
+        "SELECT id FROM adlist WHERE address = ?1",
+        params![address],
+        |row| row.get(0),
+    )?;
+    transaction.execute("DELETE FROM gravity WHERE adlist_id = ?1", params![id])?;
+    {
+        let mut insert =
+            transaction.prepare("INSERT INTO gravity (domain, adlist_id) VALUES (?1, ?2)")?;
+        for domain in &addlist.list {
+            insert.execute(params![domain, id])?;
+        }
+    }
+    transaction.execute(
+        "UPDATE adlist SET number = ?1, date_modified = cast(strftime('%s', 'now') as int) WHERE id = ?2",
+        params![addlist.list.len(), id],
+    )?;
+    transaction.commit()?;
+
+    let data = addlist.list.join("\r\n");
+    Ok(WrittenFile {
+        file: GRAVITY_DB.to_owned(),
+        size: data.len(),
+        domains: addlist.list.len(),
+        fingerprint: fingerprint(data.as_bytes()),
+    })
+}
+
+/// Lists the `gravity.db` once instead of once per addlist stored in it.
+///
+/// It is listed with the domains of all these addlists, and the size and fingerprint of the database.
+///
+/// # Errors
+/// - If the database could not be read.
+pub fn merge(path: &str, files: Vec<WrittenFile>) -> std::io::Result<Vec<WrittenFile>> {
+    let (stored, mut files): (Vec<_>, Vec<_>) =
+        files.into_iter().partition(|file| file.file == GRAVITY_DB);
+    if stored.is_empty() {
+        return Ok(files);
+    }
+    let data = fs::read(format!("{}/{}", path, GRAVITY_DB))?;
+    files.push(WrittenFile {
+        file: GRAVITY_DB.to_owned(),
+        size: data.len(),
+        domains: stored.iter().map(|file| file.domains).sum(),
+        fingerprint: fingerprint(&data),
+    });
+    Ok(files)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::GRAVITY_DB;
+    use crate::data::Addlist;
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_write_to_db_replaces() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut addlist = Addlist {
+            name: "Addlist".to_owned(),
+            list: vec!["a.com".to_owned(), "b.com".to_owned()],
+            ..Default::default()
+        };
+        super::write_to_db(path, &addlist).unwrap();
+        addlist.list = vec!["c.com".to_owned()];
+        let have = super::write_to_db(path, &addlist).unwrap();
+
+        let connection = Connection::open(dir.path().join(GRAVITY_DB)).unwrap();
+        let domains: Vec<String> = connection
+            .prepare(
+                "SELECT domain FROM gravity JOIN adlist ON adlist.id = adlist_id \
+                 WHERE address = 'phda://Addlist'",
+            )
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let number: usize = connection
+            .query_row("SELECT number FROM adlist", [], |row| row.get(0))
+            .unwrap();
+
+        assert_eq!(vec!["c.com".to_owned()], domains);
+        assert_eq!(1, number);
+        assert_eq!(1, have.domains);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<(), String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut files = Vec::new();
+        for (name, list) in [("A", vec!["a.com"]), ("B", vec!["b.com", "c.com"])] {
+            let addlist = Addlist {
+                name: name.to_owned(),
+                list: list.into_iter().map(str::to_owned).collect(),
+                ..Default::default()
+            };
+            files.push(super::write_to_db(path, &addlist).unwrap());
+        }
+
+        let have = super::merge(path, files).unwrap();
+
+        assert_eq!(1, have.len());
+        assert_eq!(GRAVITY_DB, have[0].file);
+        assert_eq!(3, have[0].domains);
+        Ok(())
+    }
+}
@@ -1,27 +1,127 @@
 use anyhow::{anyhow, Error};
 use core::num::NonZeroUsize;
+use log::{debug, error, warn};
 use std::{
     cmp::Ordering,
-    sync::{mpsc, Arc, Mutex},
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
 };
-use worker::{Message, Worker};
+use worker::{Counters, Message, Worker};
+
+/// Called with the number of completed and of all submitted jobs whenever a job is completed.
+pub type Progress = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Interval in which a shutdown checks whether the workers are finished.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Number of times the process was asked to stop, see [request_stop].
+static STOPS: AtomicUsize = AtomicUsize::new(0);
+
+/// Asks everything that currently observes a [StopSignal] to stop,
+/// ThreadPools refuse new jobs while the jobs already running still complete.
+///
+/// Signals created afterwards are not affected, so a later run starts as usual.
+pub fn request_stop() {
+    STOPS.fetch_add(1, AtomicOrdering::SeqCst);
+}
+
+/// Whether the process was ever asked to stop.
+pub fn stop_requested() -> bool {
+    STOPS.load(AtomicOrdering::SeqCst) > 0
+}
+
+/// Tells whether a stop was requested since the signal was created.
+#[derive(Clone, Copy)]
+pub struct StopSignal {
+    stops: &'static AtomicUsize,
+    since: usize,
+}
+
+impl StopSignal {
+    /// Observes the stops requested by [request_stop] from now on.
+    pub fn new() -> StopSignal {
+        StopSignal::of(&STOPS)
+    }
+
+    /// Observes the stops counted by the given counter from now on.
+    pub fn of(stops: &'static AtomicUsize) -> StopSignal {
+        StopSignal {
+            stops,
+            since: stops.load(AtomicOrdering::SeqCst),
+        }
+    }
+
+    /// Whether a stop was requested since the signal was created.
+    pub fn is_stopped(&self) -> bool {
+        self.stops.load(AtomicOrdering::SeqCst) != self.since
+    }
+}
+
+impl Default for StopSignal {
+    fn default() -> Self {
+        StopSignal::new()
+    }
+}
 
 pub struct ThreadPool {
     workers: Vec<Worker>,
     sender: mpsc::Sender<Message>,
+    counters: Arc<Counters>,
+    /// Whether the terminate messages were already sent.
+    terminated: bool,
+    /// Once stopped, new jobs are refused.
+    stop: StopSignal,
+}
+
+/// Snapshot of the current ThreadPool utilisation.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PoolStats {
+    pub workers: usize,
+    /// Jobs waiting for a free worker.
+    pub queued: usize,
+    /// Jobs currently executed by a worker.
+    pub active: usize,
 }
 
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
     /// The size is the number of threads in the pool.
+    /// With `all_cores` the pool is sized by all instead of a half of all logical cores.
     ///
     /// # Errors
-    /// The ThreadPool creation failes when the number of threads grather than a half of all logical cores.
-    pub fn new(threads: Option<NonZeroUsize>) -> Result<ThreadPool, Error> {
+    /// The ThreadPool creation failes when the number of threads grather than a half of all logical cores,
+    /// unless `all_cores` is set.
+    pub fn new(threads: Option<NonZeroUsize>, all_cores: bool) -> Result<ThreadPool, Error> {
+        ThreadPool::create(threads, all_cores, None)
+    }
+
+    /// Create a new ThreadPool like [ThreadPool::new] that reports every completed job to `progress`.
+    ///
+    /// # Errors
+    /// The same as [ThreadPool::new].
+    pub fn new_with_progress(
+        threads: Option<NonZeroUsize>,
+        all_cores: bool,
+        progress: Progress,
+    ) -> Result<ThreadPool, Error> {
+        ThreadPool::create(threads, all_cores, Some(progress))
+    }
+
+    fn create(
+        threads: Option<NonZeroUsize>,
+        all_cores: bool,
+        progress: Option<Progress>,
+    ) -> Result<ThreadPool, Error> {
         let capacity = {
             let limit = num_cpus::get() / 2;
             match threads {
+                Some(threads) if all_cores => threads.get(),
+                None if all_cores => num_cpus::get(),
                 Some(threads) => match limit.cmp(&threads.get()) {
                     Ordering::Less | Ordering::Equal => threads.get(),
                     Ordering::Greater => {
@@ -36,24 +136,101 @@ impl ThreadPool {
 
         let receiver = Arc::new(Mutex::new(receiver));
 
+        let counters = Arc::new(Counters::default());
+
         let mut workers = Vec::with_capacity(capacity);
 
         for id in 0..capacity {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(
+                id,
+                Arc::clone(&receiver),
+                Arc::clone(&counters),
+                progress.clone(),
+            ));
         }
 
-        Ok(ThreadPool { workers, sender })
+        Ok(ThreadPool {
+            workers,
+            sender,
+            counters,
+            terminated: false,
+            stop: StopSignal::new(),
+        })
+    }
+
+    /// Refuses new jobs once the given signal is stopped instead of the one created with the pool.
+    pub fn with_stop(mut self, stop: StopSignal) -> ThreadPool {
+        self.stop = stop;
+        self
     }
 
+    /// Returns the current number of workers, queued and active jobs.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            workers: self.workers.len(),
+            queued: self.counters.queued.load(AtomicOrdering::SeqCst),
+            active: self.counters.active.load(AtomicOrdering::SeqCst),
+        }
+    }
+
+    /// Submits the job to the next free worker.
+    ///
+    /// Once a stop was requested the job is dropped instead, see [request_stop].
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
+        if self.stop.is_stopped() {
+            debug!("Stop requested, refusing a new job.");
+            return;
+        }
         let job = Box::new(f);
 
+        self.counters.queued.fetch_add(1, AtomicOrdering::SeqCst);
+        self.counters.submitted.fetch_add(1, AtomicOrdering::SeqCst);
         if let Err(err) = self.sender.send(Message::NewJob(job)) {
-            println!("{:?}", err);
+            self.counters.queued.fetch_sub(1, AtomicOrdering::SeqCst);
+            self.counters.submitted.fetch_sub(1, AtomicOrdering::SeqCst);
+            error!("{:?}", err);
+        }
+    }
+
+    /// Terminates all workers and waits at most the timeout for them to finish.
+    ///
+    /// Workers that are still running after the timeout are detached.
+    /// Returns the ids of the detached workers.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Vec<usize> {
+        self.terminate();
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && self.workers.iter().any(Worker::is_running) {
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+
+        let mut detached = Vec::new();
+        for worker in &mut self.workers {
+            if worker.is_running() {
+                warn!(
+                    "Worker {} did not finish in time and is detached.",
+                    worker.id
+                );
+                worker.thread = None;
+                detached.push(worker.id);
+            }
+        }
+        // The finished workers are joined when the ThreadPool is dropped.
+        detached
+    }
+
+    fn terminate(&mut self) {
+        if self.terminated {
+            return;
+        }
+        debug!("Sending terminate message to all workers.");
+
+        for _ in &self.workers {
+            self.sender.send(Message::Terminate).unwrap_or_default();
         }
+        self.terminated = true;
     }
 }
 
@@ -62,21 +239,17 @@ impl ThreadPool {
 /// The TheadPool will only be dropped when all workers are finished.
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        println!("Sending terminate message to all workers.");
+        self.terminate();
 
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap_or_default();
-        }
-
-        println!("Shutting down all workers.");
+        debug!("Shutting down all workers.");
 
         for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+            debug!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
                 match thread.join() {
-                    Ok(_) => println!("Worker {} closes with success!", worker.id),
-                    Err(err) => eprintln!(
+                    Ok(_) => debug!("Worker {} closes with success!", worker.id),
+                    Err(err) => error!(
                         "Worker {} failed to closes with error: {:?}",
                         worker.id, err
                     ),
@@ -87,6 +260,10 @@ impl Drop for ThreadPool {
 }
 
 mod worker {
+    use super::Progress;
+    use log::{debug, error, warn};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::mpsc;
     use std::sync::Arc;
     use std::sync::Mutex;
@@ -99,34 +276,58 @@ mod worker {
         Terminate,
     }
 
+    /// Job counters shared between the ThreadPool and its workers.
+    #[derive(Default)]
+    pub struct Counters {
+        pub queued: AtomicUsize,
+        pub active: AtomicUsize,
+        pub submitted: AtomicUsize,
+        pub completed: AtomicUsize,
+    }
+
     pub struct Worker {
         pub id: usize,
         pub thread: Option<thread::JoinHandle<()>>,
     }
 
     impl Worker {
-        pub fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
+        pub fn new(
+            id: usize,
+            receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+            counters: Arc<Counters>,
+            progress: Option<Progress>,
+        ) -> Worker {
             let thread = thread::spawn(move || loop {
                 let message = receiver.lock().map(|guard| guard.recv());
 
                 match message {
                     //Expected cases
                     Ok(Ok(Message::NewJob(job))) => {
-                        println!("Worker {} got a job; executing.", id);
-                        job();
-                        println!("Worker {} finished a job; waiting for job.", id);
+                        debug!("Worker {} got a job; executing.", id);
+                        counters.active.fetch_add(1, Ordering::SeqCst);
+                        counters.queued.fetch_sub(1, Ordering::SeqCst);
+                        // A panicking job must not take the worker down with it.
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            warn!("Worker {} caught a panicking job; continuing.", id);
+                        }
+                        counters.active.fetch_sub(1, Ordering::SeqCst);
+                        let completed = counters.completed.fetch_add(1, Ordering::SeqCst) + 1;
+                        if let Some(progress) = &progress {
+                            progress(completed, counters.submitted.load(Ordering::SeqCst));
+                        }
+                        debug!("Worker {} finished a job; waiting for job.", id);
                     }
                     Ok(Ok(Message::Terminate)) => {
-                        println!("Worker {} was told to terminate.", id);
+                        debug!("Worker {} was told to terminate.", id);
                         break;
                     }
                     // Error cases
                     Ok(Err(_)) => {
-                        eprintln!("Sender has disconnected. Worker {} terminates now!", id);
+                        error!("Sender has disconnected. Worker {} terminates now!", id);
                         break;
                     }
                     Err(_) => {
-                        eprintln!("Another Worker panicked. Worker {} terminates now!", id);
+                        error!("Another Worker panicked. Worker {} terminates now!", id);
                         break;
                     }
                 }
@@ -137,5 +338,179 @@ mod worker {
                 thread: Some(thread),
             }
         }
+
+        /// Whether the thread of the worker is still running.
+        pub fn is_running(&self) -> bool {
+            self.thread
+                .as_ref()
+                .is_some_and(|thread| !thread.is_finished())
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{PoolStats, StopSignal, ThreadPool};
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Waits until the pool reports the wanted stats or a timeout is reached.
+    fn wait_for(pool: &ThreadPool, want: PoolStats) -> PoolStats {
+        let start = Instant::now();
+        while pool.stats() != want && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        pool.stats()
+    }
+
+    #[test]
+    fn test_stats() -> Result<(), String> {
+        let workers = num_cpus::get();
+        let pool =
+            ThreadPool::new(NonZeroUsize::new(workers), false).map_err(|err| err.to_string())?;
+        let (sender, receiver) = mpsc::channel::<()>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..workers + 2 {
+            let receiver = receiver.clone();
+            pool.execute(move || {
+                receiver.lock().map(|receiver| receiver.recv()).ok();
+            });
+        }
+
+        let busy = PoolStats {
+            workers,
+            queued: 2,
+            active: workers,
+        };
+        assert_eq!(busy, wait_for(&pool, busy));
+
+        for _ in 0..workers + 2 {
+            sender.send(()).unwrap();
+        }
+
+        let idle = PoolStats {
+            workers,
+            queued: 0,
+            active: 0,
+        };
+        assert_eq!(idle, wait_for(&pool, idle));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shutdown_timeout() -> Result<(), String> {
+        let workers = num_cpus::get();
+        let pool =
+            ThreadPool::new(NonZeroUsize::new(workers), false).map_err(|err| err.to_string())?;
+        let (sender, receiver) = mpsc::channel::<()>();
+        pool.execute(move || {
+            receiver.recv().ok();
+        });
+        wait_for(
+            &pool,
+            PoolStats {
+                workers,
+                queued: 0,
+                active: 1,
+            },
+        );
+
+        let start = Instant::now();
+        let detached = pool.shutdown_timeout(Duration::from_millis(100));
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(1, detached.len());
+        sender.send(()).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_cores() -> Result<(), String> {
+        let workers = num_cpus::get();
+        let pool =
+            ThreadPool::new(NonZeroUsize::new(workers), true).map_err(|err| err.to_string())?;
+        assert_eq!(workers, pool.stats().workers);
+
+        let pool = ThreadPool::new(None, true).map_err(|err| err.to_string())?;
+        assert_eq!(workers, pool.stats().workers);
+        Ok(())
+    }
+
+    #[test]
+    fn test_panicking_job() -> Result<(), String> {
+        let pool = ThreadPool::new(NonZeroUsize::new(1), true).map_err(|err| err.to_string())?;
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(|| panic!("Job failed"));
+        pool.execute(move || sender.send(()).unwrap());
+
+        receiver
+            .recv_timeout(Duration::from_secs(5))
+            .map_err(|err| err.to_string())?;
+        let idle = PoolStats {
+            workers: 1,
+            queued: 0,
+            active: 0,
+        };
+        assert_eq!(idle, wait_for(&pool, idle));
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress() -> Result<(), String> {
+        let jobs = 5;
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let progress = {
+            let observed = observed.clone();
+            Arc::new(move |completed, total| observed.lock().unwrap().push((completed, total)))
+        };
+        let pool = ThreadPool::new_with_progress(NonZeroUsize::new(2), true, progress)
+            .map_err(|err| err.to_string())?;
+
+        for _ in 0..jobs {
+            pool.execute(|| {});
+        }
+        drop(pool);
+
+        let observed = observed.lock().unwrap();
+        assert_eq!(jobs, observed.len());
+        assert_eq!(Some(&(jobs, jobs)), observed.iter().max());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stop_refuses_new_jobs() -> Result<(), String> {
+        static STOPS: AtomicUsize = AtomicUsize::new(0);
+        let pool = ThreadPool::new(NonZeroUsize::new(1), true)
+            .map_err(|err| err.to_string())?
+            .with_stop(StopSignal::of(&STOPS));
+        let completed = Arc::new(AtomicUsize::new(0));
+        let (release, gate) = mpsc::channel::<()>();
+
+        let running = completed.clone();
+        pool.execute(move || {
+            gate.recv().unwrap();
+            running.fetch_add(1, Ordering::SeqCst);
+        });
+        let queued = completed.clone();
+        pool.execute(move || {
+            queued.fetch_add(1, Ordering::SeqCst);
+        });
+        STOPS.fetch_add(1, Ordering::SeqCst);
+        let refused = completed.clone();
+        pool.execute(move || {
+            refused.fetch_add(1, Ordering::SeqCst);
+        });
+        release.send(()).unwrap();
+        // Waits until all workers are finished.
+        drop(pool);
+
+        assert_eq!(2, completed.load(Ordering::SeqCst));
+        Ok(())
     }
 }
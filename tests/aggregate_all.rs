@@ -0,0 +1,96 @@
+use mockito::mock;
+use phda::aggregate_all;
+use phda::config::Config;
+use phda::data::{Addlist, AddlistSources};
+use std::collections::{HashMap, HashSet};
+
+#[test]
+fn test_aggregate_all() -> Result<(), String> {
+    let ads = mock("GET", "/aggregate-ads")
+        .with_status(200)
+        .with_body("docs.rs\ncrates.io")
+        .create();
+    let tracking = mock("GET", "/aggregate-tracking")
+        .with_status(200)
+        .with_body("rust-lang.org")
+        .create();
+    let sources = |url: &str| AddlistSources {
+        addlist: HashSet::from([mockito::server_url() + url]),
+        ..Default::default()
+    };
+    let config = Config {
+        path: "./does-not-exist".to_owned(),
+        prefix: None,
+        suffix: None,
+        www_duplication: Some(false),
+        whitelist: None,
+        addlist: HashMap::from([
+            ("Tracking".to_owned(), sources("/aggregate-tracking")),
+            ("Ads".to_owned(), sources("/aggregate-ads")),
+        ]),
+        ..Default::default()
+    };
+
+    let have = aggregate_all(config).map_err(|err| err.to_string())?;
+
+    ads.assert();
+    tracking.assert();
+    let want = vec![
+        Addlist {
+            name: "Ads".to_owned(),
+            list: vec!["crates.io".to_owned(), "docs.rs".to_owned()],
+            ..Default::default()
+        },
+        Addlist {
+            name: "Tracking".to_owned(),
+            list: vec!["rust-lang.org".to_owned()],
+            ..Default::default()
+        },
+    ];
+    assert_eq!(want, have);
+    Ok(())
+}
+
+#[test]
+fn test_aggregate_all_writes_nothing() -> Result<(), String> {
+    let mocked = mock("GET", "/aggregate-cached")
+        .with_status(200)
+        .with_body("docs.rs")
+        .create();
+    let dir = tempfile::tempdir().unwrap();
+    let cache = |name: &str| Some(dir.path().join(name).to_str().unwrap().to_owned());
+    let config = Config {
+        path: "./does-not-exist".to_owned(),
+        whitelist: None,
+        validation_cache: cache("validation"),
+        http_cache: cache("http"),
+        addlist: HashMap::from([(
+            "Addlist".to_owned(),
+            AddlistSources {
+                addlist: HashSet::from([mockito::server_url() + "/aggregate-cached"]),
+                ..Default::default()
+            },
+        )]),
+        ..Default::default()
+    };
+
+    aggregate_all(config).map_err(|err| err.to_string())?;
+
+    mocked.assert();
+    assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+    Ok(())
+}
+
+#[test]
+fn test_aggregate_all_invalid() -> Result<(), String> {
+    let config = Config {
+        addlist: HashMap::new(),
+        ..Default::default()
+    };
+
+    let err = aggregate_all(config)
+        .err()
+        .ok_or("Accepted an invalid config!")?;
+    assert!(err.to_string().contains("No addlist"), "{err}");
+    Ok(())
+}